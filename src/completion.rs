@@ -0,0 +1,438 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    sync::Mutex,
+};
+
+use lazy_static::lazy_static;
+
+use crate::{
+    hash,
+    interpreter::{
+        environment::{get_var, get_var_names, split_assignment},
+        executor::BUILTINS,
+    },
+    utils::POISONED_LOCK_MSG_ERR,
+};
+
+lazy_static! {
+    // What `complete` registered for the arguments of each command
+    static ref SPECS: Mutex<RefCell<BTreeMap<String, CompletionSpec>>> =
+        Mutex::new(RefCell::new(BTreeMap::new()));
+}
+
+/// Where the candidates for the arguments of a command come from. With nothing chosen, the
+/// arguments are completed as files.
+#[derive(Clone, Default, PartialEq)]
+pub struct CompletionSpec {
+    pub words: Vec<String>,
+    pub files: bool,
+    pub directories: bool,
+    pub commands: bool,
+    pub variables: bool,
+}
+
+impl std::fmt::Display for CompletionSpec {
+    /// The spec as the `complete` options that create it.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut options = vec![];
+        if !self.words.is_empty() {
+            options.push(format!("-W '{}'", self.words.join(" ")));
+        }
+        for (chosen, option) in [
+            (self.files, "-f"),
+            (self.directories, "-d"),
+            (self.commands, "-c"),
+            (self.variables, "-v"),
+        ] {
+            if chosen {
+                options.push(option.to_owned());
+            }
+        }
+
+        write!(f, "{}", options.join(" "))
+    }
+}
+
+pub fn register(command: &str, spec: CompletionSpec) {
+    let specs = SPECS.lock().expect(POISONED_LOCK_MSG_ERR);
+    specs.borrow_mut().insert(command.to_owned(), spec);
+}
+
+/// Forgets the spec of a command, returns if there was any.
+pub fn unregister(command: &str) -> bool {
+    let specs = SPECS.lock().expect(POISONED_LOCK_MSG_ERR);
+    specs.borrow_mut().remove(command).is_some()
+}
+
+pub fn specs() -> Vec<(String, CompletionSpec)> {
+    let specs = SPECS.lock().expect(POISONED_LOCK_MSG_ERR);
+    specs
+        .borrow()
+        .iter()
+        .map(|(command, spec)| (command.clone(), spec.clone()))
+        .collect()
+}
+
+/// Finds what the word before the cursor can become. `text` is the line up to the cursor, the
+/// result is the char where the part to replace starts and the candidates to replace it with,
+/// already escaped. The directories end with `/`.
+pub fn complete(text: &str) -> (usize, Vec<String>) {
+    let context = Context::scan(text);
+
+    if let Some((start, name, braced)) = variable_at_end(&context.word) {
+        let candidates = get_var_names()
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(name))
+            .map(|candidate| if braced { candidate + "}" } else { candidate })
+            .collect();
+        return (context.start + start, candidates);
+    }
+
+    let word = unquote(&context.word);
+    let candidates = match context.position {
+        Position::Descriptor => vec![],
+        Position::Redirect => files(&word, false, false),
+        Position::Command => {
+            // the background mark of the parser is not part of the name
+            if let Some(word) = word.strip_prefix('^') {
+                return (context.start + 1, commands(word));
+            }
+            commands(&word)
+        }
+        Position::Argument(command) => {
+            let spec = {
+                let specs = SPECS.lock().expect(POISONED_LOCK_MSG_ERR);
+                specs.borrow().get(&command).cloned()
+            };
+            match spec {
+                Some(spec) => candidates_of(&spec, &word),
+                None => files(&word, false, false),
+            }
+        }
+    };
+
+    (context.start, candidates)
+}
+
+/// The longest start that every candidate shares.
+pub fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+
+    let mut prefix = first.as_str();
+    for candidate in &candidates[1..] {
+        let len = prefix
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(candidate.len()), |((i, _), _)| i);
+        prefix = &prefix[..len];
+    }
+
+    prefix.to_owned()
+}
+
+/// What the word being completed is for.
+enum Position {
+    Command,
+    // the argument of the named command
+    Argument(String),
+    Redirect,
+    // the fd a redirect duplicates to, as in 2>@1
+    Descriptor,
+}
+
+struct Context {
+    position: Position,
+    // the word before the cursor as typed, and the char where it starts
+    word: String,
+    start: usize,
+}
+
+impl Context {
+    /// Goes through the line as the parser would, just enough to know in which word of which
+    /// command the cursor is.
+    fn scan(text: &str) -> Self {
+        let chars = text.chars().collect::<Vec<_>>();
+        let mut command: Option<String> = None;
+        let mut redirect = false;
+        let mut descriptor = false;
+        let mut word = String::new();
+        let mut start = 0;
+        let mut quote = None;
+
+        // a word is over, it's the command name unless it's an assignment or a redirect target
+        let end_word = |word: &mut String, command: &mut Option<String>, redirect: &mut bool| {
+            if word.is_empty() {
+                return;
+            }
+            if *redirect {
+                *redirect = false;
+            } else if command.is_none() && split_assignment(word).is_none() {
+                *command = Some(unquote(word.trim_start_matches('^')));
+            }
+            word.clear();
+        };
+
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if let Some(q) = quote {
+                if c == q {
+                    quote = None;
+                }
+                word.push(c);
+                i += 1;
+                continue;
+            }
+
+            match c {
+                '\'' | '"' => {
+                    quote = Some(c);
+                    word.push(c);
+                }
+                '\\' => {
+                    word.push(c);
+                    if let Some(&next) = chars.get(i + 1) {
+                        word.push(next);
+                        i += 1;
+                    }
+                }
+                ' ' | '\t' | '\n' => {
+                    end_word(&mut word, &mut command, &mut redirect);
+                    start = i + 1;
+                }
+                // duplicating an fd, as in 2>@1, the fd is the end of the redirect
+                '@' if redirect && word.is_empty() && chars[i - 1] == '>' => {
+                    while chars.get(i + 1).is_some_and(char::is_ascii_digit) {
+                        i += 1;
+                    }
+                    descriptor = i + 1 == chars.len();
+                    redirect = false;
+                    start = i + 1;
+                }
+                '|' | ';' | '&' => {
+                    end_word(&mut word, &mut command, &mut redirect);
+                    command = None;
+                    redirect = false;
+                    start = i + 1;
+                }
+                '<' | '>' => {
+                    // the number before is the fd being redirected, not a word
+                    if !word.is_empty() && word.chars().all(|c| c.is_ascii_digit()) {
+                        word.clear();
+                    }
+                    end_word(&mut word, &mut command, &mut redirect);
+                    redirect = true;
+                    start = i + 1;
+                }
+                _ => word.push(c),
+            }
+            i += 1;
+        }
+
+        let position = match command {
+            _ if descriptor => Position::Descriptor,
+            _ if redirect => Position::Redirect,
+            None => Position::Command,
+            Some(command) => Position::Argument(command),
+        };
+
+        Self {
+            position,
+            word,
+            start,
+        }
+    }
+}
+
+/// If the word ends in a `$name` or `${name` being typed, where the name starts in the word,
+/// the name and if it is in braces.
+fn variable_at_end(word: &str) -> Option<(usize, &str, bool)> {
+    let dollar = word.rfind('$')?;
+    let (name, braced) = match word[dollar + 1..].strip_prefix('{') {
+        Some(name) => (name, true),
+        None => (&word[dollar + 1..], false),
+    };
+
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    if word[..dollar].matches('\'').count() % 2 == 1 {
+        // inside single quotes it's just a dollar
+        return None;
+    }
+
+    let start = word[..word.len() - name.len()].chars().count();
+    Some((start, name, braced))
+}
+
+fn candidates_of(spec: &CompletionSpec, word: &str) -> Vec<String> {
+    let mut candidates = spec
+        .words
+        .iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .map(|candidate| escape(candidate))
+        .collect::<BTreeSet<_>>();
+
+    if spec.files || spec.directories {
+        candidates.extend(files(word, !spec.files, false));
+    }
+    if spec.commands {
+        candidates.extend(commands(word));
+    }
+    if spec.variables {
+        candidates.extend(
+            get_var_names()
+                .into_iter()
+                .filter(|candidate| candidate.starts_with(word)),
+        );
+    }
+
+    candidates.into_iter().collect()
+}
+
+/// The builtins and the executables on PATH starting with the word. With a `/` it's a path,
+/// then the candidates are the executables and the directories there.
+fn commands(word: &str) -> Vec<String> {
+    if word.contains('/') {
+        return files(word, false, true);
+    }
+
+    let mut names = BUILTINS
+        .iter()
+        .map(|&name| name.to_owned())
+        .collect::<BTreeSet<_>>();
+    names.extend(hash::executable_names());
+
+    names
+        .into_iter()
+        .filter(|name| !name.is_empty() && name.starts_with(word))
+        .map(|name| escape(&name))
+        .collect()
+}
+
+/// The entries of the directory in the word whose name starts with the rest of it. The hidden
+/// ones only when asked with a dot.
+fn files(word: &str, only_directories: bool, only_executables: bool) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+
+    let read_dir = match dir.strip_prefix("~/") {
+        Some(rest) => format!("{}/{rest}", get_var("HOME").unwrap_or_default()),
+        None if dir.is_empty() => ".".to_owned(),
+        None => dir.to_owned(),
+    };
+    let Ok(entries) = fs::read_dir(read_dir) else {
+        return vec![];
+    };
+
+    let mut candidates = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+
+            // following the links, a link to a directory is completed as one
+            let path = entry.path();
+            let is_dir = path.is_dir();
+            if (only_directories && !is_dir)
+                || (only_executables && !is_dir && !is_executable(&path))
+            {
+                return None;
+            }
+
+            let candidate = escape(&format!("{dir}{name}"));
+            Some(if is_dir { candidate + "/" } else { candidate })
+        })
+        .collect::<Vec<_>>();
+
+    candidates.sort();
+    candidates
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Puts a backslash before what the parser would take as something else than part of the
+/// word. A `~` at the start is kept, it's meant to be expanded.
+fn escape(word: &str) -> String {
+    let mut escaped = String::new();
+    for (i, c) in word.chars().enumerate() {
+        if (c == '~' && i > 0) || " \t\n'\"\\$&|;<>()*?[]#`!{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// The word as the command will see it, without quotes nor backslashes.
+fn unquote(word: &str) -> String {
+    let mut unquoted = String::new();
+    let mut quote = None;
+    let mut chars = word.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None | Some('"'), '\\') => {
+                if let Some(next) = chars.next() {
+                    unquoted.push(next);
+                }
+            }
+            _ => unquoted.push(c),
+        }
+    }
+
+    unquoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(text: &str) -> (Position, String) {
+        let context = Context::scan(text);
+        (context.position, context.word)
+    }
+
+    #[test]
+    fn knows_what_the_word_is_for() {
+        assert!(matches!(position("ec"), (Position::Command, w) if w == "ec"));
+        assert!(matches!(position("A=1 ^ec"), (Position::Command, w) if w == "^ec"));
+        assert!(
+            matches!(position("ls -l | grep 'a b' x"), (Position::Argument(c), w) if c == "grep" && w == "x")
+        );
+        assert!(matches!(position("ls; "), (Position::Command, w) if w.is_empty()));
+
+        assert!(matches!(position("ls > fi"), (Position::Redirect, w) if w == "fi"));
+        assert!(matches!(position("ls 2>fi"), (Position::Redirect, w) if w == "fi"));
+        assert!(matches!(position("ls > @fi"), (Position::Redirect, w) if w == "@fi"));
+        assert!(
+            matches!(position("ls > out x"), (Position::Argument(c), w) if c == "ls" && w == "x")
+        );
+
+        // the fd duplicated to is all of the redirect
+        assert!(matches!(position("ls 2>@"), (Position::Descriptor, _)));
+        assert!(matches!(position("ls 2>@1"), (Position::Descriptor, _)));
+        assert!(
+            matches!(position("ls 2>@1 x"), (Position::Argument(c), w) if c == "ls" && w == "x")
+        );
+        assert!(matches!(position("2>@1 l"), (Position::Command, w) if w == "l"));
+        assert!(matches!(position("ls 2>@1 | gr"), (Position::Command, w) if w == "gr"));
+    }
+}
@@ -0,0 +1,931 @@
+use std::{
+    io::{self, Write},
+    mem,
+    os::fd::{AsRawFd, BorrowedFd},
+};
+
+use nix::{
+    errno::Errno,
+    libc,
+    poll::{PollFd, PollFlags, PollTimeout, poll},
+    sys::termios::{
+        InputFlags, LocalFlags, SetArg, SpecialCharacterIndices, Termios, tcgetattr, tcsetattr,
+    },
+    unistd::read,
+};
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    completion, history,
+    interpreter::environment::{get_var, is_option_set},
+    prompt::{END_INVISIBLE, START_INVISIBLE},
+    utils::{POISONED_LOCK_MSG_ERR, STDIN, STDOUT},
+};
+
+/// Edits the lines typed in the terminal, with the emacs bindings or the vi modes depending
+/// on `set -o`.
+pub struct Editor {
+    // what the last kill removed, for Ctrl-Y and p
+    kill_buffer: Vec<char>,
+    // the history as it was when the line started, and where Up and Down are in it
+    history: Vec<String>,
+    history_index: usize,
+    // the line being typed, kept while looking at the history
+    edited: Vec<char>,
+    // the row of the cursor, counting from the last line of the prompt, as long lines wrap
+    cursor_row: usize,
+}
+
+#[derive(Default)]
+struct Line {
+    chars: Vec<char>,
+    // in chars, not bytes
+    cursor: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Alt(char),
+    Escape,
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Unknown,
+}
+
+/// What a key did to the line being edited.
+enum Action {
+    Continue,
+    Accept,
+    EndOfFile,
+    Interrupt,
+    Search,
+    Complete,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self {
+            kill_buffer: vec![],
+            history: vec![],
+            history_index: 0,
+            edited: vec![],
+            cursor_row: 0,
+        }
+    }
+
+    /// Reads a line from the terminal letting the user edit it. It works as
+    /// `BufRead::read_line`, the line is appended to the buffer with its newline and the
+    /// result is how many bytes were read, 0 at the end of file. Ctrl-C discards the line with
+    /// an `Interrupted` error.
+    pub fn read_line(&mut self, prompt: &str, buffer: &mut String) -> io::Result<usize> {
+        // Nobody else reads the standard input while the user types
+        let _stdin = STDIN.lock().expect(POISONED_LOCK_MSG_ERR);
+        let _raw_mode = RawMode::enable()?;
+
+        let vi = is_option_set("vi");
+        let mut line = Line::default();
+        self.history = history::lines();
+        self.history_index = self.history.len();
+        // vi starts inserting too, Escape goes to the command mode
+        let mut inserting = true;
+
+        write_out(&visible(prompt))?;
+        self.cursor_row = 0;
+        loop {
+            let Some(key) = read_key()? else {
+                // the terminal is gone
+                return Ok(0);
+            };
+
+            // Typing fast, Escape and the next key come together as if it was Alt
+            let key = match key {
+                Key::Alt(c) if vi && inserting => {
+                    self.insert_mode_key(Key::Escape, &mut line, vi, &mut inserting);
+                    Key::Char(c)
+                }
+                key => key,
+            };
+
+            let mut action = if inserting {
+                self.insert_mode_key(key, &mut line, vi, &mut inserting)
+            } else {
+                self.command_mode_key(key, &mut line, &mut inserting)?
+            };
+            match action {
+                Action::Search => action = self.search(&mut line)?,
+                Action::Complete => action = self.complete(prompt, &mut line)?,
+                _ => {}
+            }
+            if !inserting && line.cursor > 0 && line.cursor >= line.chars.len() {
+                // in command mode the cursor is always on a char
+                line.cursor = line.chars.len() - 1;
+            }
+
+            match action {
+                Action::Continue | Action::Search | Action::Complete => {
+                    self.refresh(prompt, &line)?
+                }
+                Action::Accept => {
+                    self.leave_line(prompt, &line)?;
+                    write_out("\r\n")?;
+                    let text = line.chars.iter().collect::<String>();
+                    buffer.push_str(&text);
+                    buffer.push('\n');
+                    return Ok(text.len() + 1);
+                }
+                Action::EndOfFile => {
+                    self.leave_line(prompt, &line)?;
+                    write_out("\r\n")?;
+                    return Ok(0);
+                }
+                Action::Interrupt => {
+                    self.leave_line(prompt, &line)?;
+                    write_out("^C\r\n")?;
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+            }
+        }
+    }
+
+    /// The keys of emacs, and of the insert mode of vi.
+    fn insert_mode_key(
+        &mut self,
+        key: Key,
+        line: &mut Line,
+        vi: bool,
+        inserting: &mut bool,
+    ) -> Action {
+        match key {
+            Key::Char(c) => line.insert(&[c]),
+            Key::Enter => return Action::Accept,
+            Key::Ctrl('c') => return Action::Interrupt,
+            Key::Ctrl('d') if line.chars.is_empty() => return Action::EndOfFile,
+            Key::Ctrl('d') | Key::Delete => {
+                line.delete(line.cursor, line.cursor + 1);
+            }
+            Key::Backspace | Key::Ctrl('h') if line.cursor > 0 => {
+                line.delete(line.cursor - 1, line.cursor);
+            }
+            Key::Left | Key::Ctrl('b') => line.cursor = line.cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl('f') => line.cursor = (line.cursor + 1).min(line.chars.len()),
+            Key::Home | Key::Ctrl('a') => line.cursor = 0,
+            Key::End | Key::Ctrl('e') => line.cursor = line.chars.len(),
+            Key::Ctrl('k') => self.kill(line, line.cursor, line.chars.len()),
+            Key::Ctrl('u') => self.kill(line, 0, line.cursor),
+            Key::Ctrl('w') => {
+                // back to the previous blank, as the terminal does
+                let start = line.find_back(line.cursor, char::is_whitespace);
+                self.kill(line, start, line.cursor);
+            }
+            Key::Ctrl('y') => line.insert(&self.kill_buffer.clone()),
+            Key::Up | Key::Ctrl('p') => self.recall(line, true),
+            Key::Down | Key::Ctrl('n') => self.recall(line, false),
+            Key::Ctrl('r') => return Action::Search,
+            // Tab
+            Key::Ctrl('i') => return Action::Complete,
+            Key::Ctrl('l') => {
+                // clear the screen, the line is drawn again right after
+                let _ = write_out("\x1b[H\x1b[2J");
+            }
+            Key::Alt('b') => line.cursor = line.emacs_word_back(),
+            Key::Alt('f') => line.cursor = line.emacs_word_forward(),
+            Key::Alt('d') => {
+                let end = line.emacs_word_forward();
+                self.kill(line, line.cursor, end);
+            }
+            Key::Escape if vi => {
+                *inserting = false;
+                line.cursor = line.cursor.saturating_sub(1);
+            }
+            _ => {}
+        }
+
+        Action::Continue
+    }
+
+    /// The keys of the command mode of vi.
+    fn command_mode_key(
+        &mut self,
+        key: Key,
+        line: &mut Line,
+        inserting: &mut bool,
+    ) -> io::Result<Action> {
+        let c = match key {
+            Key::Char(c) => c,
+            Key::Enter => return Ok(Action::Accept),
+            Key::Ctrl('c') => return Ok(Action::Interrupt),
+            Key::Ctrl('d') if line.chars.is_empty() => return Ok(Action::EndOfFile),
+            Key::Left | Key::Backspace => 'h',
+            Key::Right => 'l',
+            Key::Home => '0',
+            Key::End => '$',
+            Key::Delete => 'x',
+            Key::Up => 'k',
+            Key::Down => 'j',
+            _ => return Ok(Action::Continue),
+        };
+
+        match c {
+            'i' => *inserting = true,
+            'a' => {
+                *inserting = true;
+                line.cursor = (line.cursor + 1).min(line.chars.len());
+            }
+            'I' => {
+                *inserting = true;
+                line.cursor = line.first_non_blank();
+            }
+            'A' => {
+                *inserting = true;
+                line.cursor = line.chars.len();
+            }
+            'k' | '-' => {
+                self.recall(line, true);
+                line.cursor = 0;
+            }
+            'j' | '+' => {
+                self.recall(line, false);
+                line.cursor = 0;
+            }
+            'x' => self.kill(line, line.cursor, line.cursor + 1),
+            'X' if line.cursor > 0 => self.kill(line, line.cursor - 1, line.cursor),
+            's' => {
+                self.kill(line, line.cursor, line.cursor + 1);
+                *inserting = true;
+            }
+            'D' | 'C' => {
+                self.kill(line, line.cursor, line.chars.len());
+                *inserting = c == 'C';
+            }
+            'S' => {
+                self.kill(line, 0, line.chars.len());
+                *inserting = true;
+            }
+            'p' | 'P' => {
+                if c == 'p' && !line.chars.is_empty() {
+                    line.cursor += 1;
+                }
+                line.insert(&self.kill_buffer.clone());
+                line.cursor = line.cursor.saturating_sub(1);
+            }
+            'r' => {
+                if let Some(Key::Char(replacement)) = read_key()?
+                    && line.cursor < line.chars.len()
+                {
+                    line.chars[line.cursor] = replacement;
+                }
+            }
+            '~' => {
+                if let Some(current) = line.chars.get_mut(line.cursor) {
+                    *current = match current.is_uppercase() {
+                        true => current.to_lowercase().next().unwrap_or(*current),
+                        false => current.to_uppercase().next().unwrap_or(*current),
+                    };
+                    line.cursor += 1;
+                }
+            }
+            // d, c and y take a motion, doubled they work on the whole line
+            'd' | 'c' | 'y' => {
+                let Some(Key::Char(motion)) = read_key()? else {
+                    return Ok(Action::Continue);
+                };
+                let (start, end) = if motion == c {
+                    (0, line.chars.len())
+                } else {
+                    let Some(target) = line.vi_motion(motion) else {
+                        return Ok(Action::Continue);
+                    };
+                    match motion {
+                        // these include the char they land on
+                        'e' | 'E' | '$' => (line.cursor, (target + 1).min(line.chars.len())),
+                        _ => (line.cursor.min(target), line.cursor.max(target)),
+                    }
+                };
+
+                if c == 'y' {
+                    self.kill_buffer = line.chars[start..end].to_vec();
+                } else {
+                    self.kill(line, start, end);
+                    *inserting = c == 'c';
+                }
+            }
+            motion => {
+                if let Some(target) = line.vi_motion(motion) {
+                    line.cursor = target;
+                }
+            }
+        }
+
+        Ok(Action::Continue)
+    }
+
+    /// Replaces the line by the previous or the next one of the history. Past the newest one
+    /// is the line that was being typed.
+    fn recall(&mut self, line: &mut Line, previous: bool) {
+        if previous && self.history_index > 0 {
+            if self.history_index == self.history.len() {
+                self.edited = line.chars.clone();
+            }
+            self.history_index -= 1;
+        } else if !previous && self.history_index < self.history.len() {
+            self.history_index += 1;
+        } else {
+            return;
+        }
+
+        line.chars = match self.history.get(self.history_index) {
+            Some(recalled) => recalled.chars().collect(),
+            None => self.edited.clone(),
+        };
+        line.cursor = line.chars.len();
+    }
+
+    /// Ctrl-R, looks back in the history for the lines containing what is typed. Ctrl-R again
+    /// goes to an older match, Enter runs the match, Ctrl-G gives the original line back and
+    /// any other key leaves the match in the line to edit it.
+    fn search(&mut self, line: &mut Line) -> io::Result<Action> {
+        let original = line.chars.clone();
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+        let mut failed = false;
+
+        loop {
+            let matched = found.map_or("", |i| self.history[i].as_str());
+            let failing = if failed { "failing " } else { "" };
+            let matched = matched.chars().collect::<Vec<_>>();
+            self.draw(
+                &format!("({failing}reverse-i-search)`{query}': "),
+                &matched,
+                matched.len(),
+            )?;
+
+            let Some(key) = read_key()? else {
+                return Ok(Action::EndOfFile);
+            };
+
+            // from where the search starts looking back, this one included
+            let from = match key {
+                Key::Char(c) => {
+                    query.push(c);
+                    found.unwrap_or(self.history.len().saturating_sub(1))
+                }
+                Key::Backspace | Key::Ctrl('h') => {
+                    query.pop();
+                    self.history.len().saturating_sub(1)
+                }
+                Key::Ctrl('r') => match found {
+                    Some(0) | None => continue,
+                    Some(i) => i - 1,
+                },
+                Key::Ctrl('g') | Key::Ctrl('c') => {
+                    line.chars = original;
+                    line.cursor = line.chars.len();
+                    return Ok(Action::Continue);
+                }
+                key => {
+                    if let Some(i) = found {
+                        self.history_index = i;
+                        line.chars = self.history[i].chars().collect();
+                        line.cursor = line.chars.len();
+                    }
+                    return Ok(match key {
+                        Key::Enter => Action::Accept,
+                        _ => Action::Continue,
+                    });
+                }
+            };
+
+            if query.is_empty() || self.history.is_empty() {
+                found = None;
+                failed = false;
+                continue;
+            }
+            match (0..=from).rev().find(|&i| self.history[i].contains(&query)) {
+                Some(i) => {
+                    found = Some(i);
+                    failed = false;
+                }
+                None => failed = true,
+            }
+        }
+    }
+
+    /// Completes the word before the cursor with what all the candidates share. When that adds
+    /// nothing, the candidates are listed below the line.
+    fn complete(&mut self, prompt: &str, line: &mut Line) -> io::Result<Action> {
+        let text = line.chars[..line.cursor].iter().collect::<String>();
+        let (start, candidates) = completion::complete(&text);
+        let typed = line.chars[start..line.cursor].len();
+
+        let replacement = match candidates.as_slice() {
+            [] => {
+                // the bell, nothing to offer
+                write_out("\x07")?;
+                return Ok(Action::Continue);
+            }
+            // a directory may go on, anything else is a whole word
+            [candidate] if candidate.ends_with('/') => candidate.clone(),
+            [candidate] => format!("{candidate} "),
+            _ => completion::common_prefix(&candidates),
+        };
+
+        if replacement.chars().count() > typed {
+            line.delete(start, line.cursor);
+            line.insert(&replacement.chars().collect::<Vec<_>>());
+        } else {
+            // below the whole line, the prompt is written again after the list
+            self.leave_line(prompt, line)?;
+            list(prompt, &candidates)?;
+            self.cursor_row = 0;
+        }
+
+        Ok(Action::Continue)
+    }
+
+    /// Draws the line again, only the last line of the prompt needs to be drawn with it.
+    fn refresh(&mut self, prompt: &str, line: &Line) -> io::Result<()> {
+        let prompt = prompt.rsplit('\n').next().unwrap_or_default();
+        self.draw(prompt, &line.chars, line.cursor)
+    }
+
+    /// Draws the line with the cursor after its end, where the output of the command goes.
+    fn leave_line(&mut self, prompt: &str, line: &Line) -> io::Result<()> {
+        let prompt = prompt.rsplit('\n').next().unwrap_or_default();
+        self.draw(prompt, &line.chars, line.chars.len())
+    }
+
+    /// Draws the text after the prefix from the row where the prefix starts, clearing what
+    /// was there, and leaves the cursor on the char at the position. When the text doesn't fit
+    /// in the width of the terminal it goes on in the rows below.
+    fn draw(&mut self, prefix: &str, text: &[char], cursor: usize) -> io::Result<()> {
+        let columns = columns();
+        let prefix_width = display_width(prefix);
+
+        let mut output = String::new();
+        if self.cursor_row > 0 {
+            output.push_str(&format!("\x1b[{}A", self.cursor_row));
+        }
+        output.push_str("\r\x1b[J");
+        output.push_str(&visible(prefix));
+        output.extend(text);
+
+        let ((mut end_row, end_column), (row, column)) =
+            layout(prefix_width, text, cursor, columns);
+        if end_column >= columns {
+            // the terminal waits for another char to wrap, the cursor goes to the next row now
+            output.push_str("\r\n");
+            end_row += 1;
+        }
+
+        if end_row > row {
+            output.push_str(&format!("\x1b[{}A", end_row - row));
+        }
+        output.push('\r');
+        if column > 0 {
+            output.push_str(&format!("\x1b[{column}C"));
+        }
+        self.cursor_row = row;
+
+        write_out(&output)
+    }
+
+    fn kill(&mut self, line: &mut Line, start: usize, end: usize) {
+        let killed = line.delete(start, end);
+        if !killed.is_empty() {
+            self.kill_buffer = killed;
+        }
+    }
+}
+
+impl Line {
+    fn insert(&mut self, chars: &[char]) {
+        self.chars
+            .splice(self.cursor..self.cursor, chars.iter().copied());
+        self.cursor += chars.len();
+    }
+
+    /// Removes the chars in the range, returning them.
+    fn delete(&mut self, start: usize, end: usize) -> Vec<char> {
+        let end = end.min(self.chars.len());
+        if start >= end {
+            return vec![];
+        }
+
+        let deleted = self.chars.drain(start..end).collect();
+        self.cursor = start;
+        deleted
+    }
+
+    /// Where the run of chars before the position that don't match starts, skipping the ones
+    /// that match right before it.
+    fn find_back(&self, position: usize, is_separator: fn(char) -> bool) -> usize {
+        let mut i = position;
+        while i > 0 && is_separator(self.chars[i - 1]) {
+            i -= 1;
+        }
+        while i > 0 && !is_separator(self.chars[i - 1]) {
+            i -= 1;
+        }
+        i
+    }
+
+    fn emacs_word_back(&self) -> usize {
+        self.find_back(self.cursor, |c| !c.is_alphanumeric())
+    }
+
+    fn emacs_word_forward(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.chars.len() && !self.chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.chars.len() && self.chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+
+    fn first_non_blank(&self) -> usize {
+        self.chars
+            .iter()
+            .position(|c| !c.is_whitespace())
+            .unwrap_or(self.chars.len())
+    }
+
+    /// Where a motion of vi takes the cursor.
+    fn vi_motion(&self, motion: char) -> Option<usize> {
+        // vi words are runs of letters, digits and underscores, or runs of the other non blanks
+        let class = |c: char| match c {
+            c if c.is_whitespace() => 0,
+            c if c.is_alphanumeric() || c == '_' => 1,
+            _ => 2,
+        };
+        let len = self.chars.len();
+        let mut i = self.cursor;
+
+        let target = match motion {
+            'h' => i.saturating_sub(1),
+            'l' | ' ' => (i + 1).min(len),
+            '0' => 0,
+            '^' => self.first_non_blank(),
+            '$' => len.saturating_sub(1),
+            'w' | 'W' => {
+                if let Some(&current) = self.chars.get(i) {
+                    while i < len && class(self.chars[i]) == class(current) && class(current) != 0 {
+                        i += 1;
+                    }
+                }
+                while i < len && class(self.chars[i]) == 0 {
+                    i += 1;
+                }
+                i
+            }
+            'b' | 'B' => {
+                while i > 0 && class(self.chars[i - 1]) == 0 {
+                    i -= 1;
+                }
+                if i > 0 {
+                    let current = class(self.chars[i - 1]);
+                    while i > 0 && class(self.chars[i - 1]) == current {
+                        i -= 1;
+                    }
+                }
+                i
+            }
+            'e' | 'E' => {
+                i += 1;
+                while i < len && class(self.chars[i]) == 0 {
+                    i += 1;
+                }
+                if i < len {
+                    let current = class(self.chars[i]);
+                    while i + 1 < len && class(self.chars[i + 1]) == current {
+                        i += 1;
+                    }
+                }
+                i.min(len.saturating_sub(1))
+            }
+            _ => return None,
+        };
+
+        Some(target)
+    }
+}
+
+/// The terminal the user types in, the shell only edits lines when it's its standard input.
+fn terminal() -> BorrowedFd<'static> {
+    // SAFETY:
+    // The fd 0 is open for the whole life of the process.
+    unsafe { BorrowedFd::borrow_raw(0) }
+}
+
+/// The terminal without its own line editing, so every key comes as soon as it's typed.
+/// The original settings are back when this is dropped.
+struct RawMode {
+    original: Termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let original = tcgetattr(terminal())?;
+
+        let mut raw = original.clone();
+        raw.local_flags
+            .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN);
+        raw.input_flags.remove(InputFlags::ICRNL | InputFlags::IXON);
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        tcsetattr(terminal(), SetArg::TCSADRAIN, &raw)?;
+
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = tcsetattr(terminal(), SetArg::TCSADRAIN, &self.original);
+    }
+}
+
+/// Reads a byte straight from the fd, the buffer of Stdin would hide from poll the bytes
+/// that are already there. None at the end of file.
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    loop {
+        match read(terminal(), &mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Tells if more bytes arrive soon, which is what tells a lonely Escape from the start of an
+/// escape sequence.
+fn has_pending_byte() -> bool {
+    let mut fds = [PollFd::new(terminal(), PollFlags::POLLIN)];
+    poll(&mut fds, PollTimeout::from(50u16)).is_ok_and(|ready| ready > 0)
+}
+
+fn read_key() -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte()? else {
+        return Ok(None);
+    };
+
+    let key = match byte {
+        0x1b if !has_pending_byte() => Key::Escape,
+        0x1b => match read_byte()? {
+            Some(b'[') | Some(b'O') => read_escape_sequence()?,
+            Some(next) => Key::Alt(next as char),
+            None => Key::Escape,
+        },
+        b'\r' | b'\n' => Key::Enter,
+        0x7f => Key::Backspace,
+        0x01..=0x1a => Key::Ctrl((byte - 1 + b'a') as char),
+        _ => {
+            // The first byte of an UTF-8 char tells how many follow it
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                match read_byte()? {
+                    Some(next) => bytes.push(next),
+                    None => break,
+                }
+            }
+
+            match String::from_utf8(bytes).ok().and_then(|c| c.chars().next()) {
+                Some(c) if !c.is_control() => Key::Char(c),
+                _ => Key::Unknown,
+            }
+        }
+    };
+
+    Ok(Some(key))
+}
+
+/// Reads what comes after `ESC [`, like `A` for Up or `3~` for Delete.
+fn read_escape_sequence() -> io::Result<Key> {
+    let mut parameters = String::new();
+    loop {
+        let Some(byte) = read_byte()? else {
+            return Ok(Key::Unknown);
+        };
+
+        match byte {
+            b'0'..=b'9' | b';' => parameters.push(byte as char),
+            b'A' => return Ok(Key::Up),
+            b'B' => return Ok(Key::Down),
+            b'C' => return Ok(Key::Right),
+            b'D' => return Ok(Key::Left),
+            b'H' => return Ok(Key::Home),
+            b'F' => return Ok(Key::End),
+            b'~' => {
+                return Ok(match parameters.as_str() {
+                    "1" | "7" => Key::Home,
+                    "4" | "8" => Key::End,
+                    "3" => Key::Delete,
+                    _ => Key::Unknown,
+                });
+            }
+            _ => return Ok(Key::Unknown),
+        }
+    }
+}
+
+/// Shows the candidates in columns below the line, then the prompt again.
+fn list(prompt: &str, candidates: &[String]) -> io::Result<()> {
+    // only the last part of the paths, the rest is already in the line
+    let names = candidates
+        .iter()
+        .map(
+            |candidate| match candidate.trim_end_matches('/').rsplit_once('/') {
+                Some((_, name)) if candidate.ends_with('/') => format!("{name}/"),
+                Some((_, name)) => name.to_owned(),
+                None => candidate.clone(),
+            },
+        )
+        .collect::<Vec<_>>();
+
+    let width = names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let per_row = (columns() / width).max(1);
+
+    let mut output = String::from("\r\n");
+    for row in names.chunks(per_row) {
+        let row = row
+            .iter()
+            .map(|name| format!("{name:<width$}"))
+            .collect::<String>();
+        output.push_str(row.trim_end());
+        output.push_str("\r\n");
+    }
+    // the line is drawn again after the prompt
+    output.push_str(&visible(prompt).replace('\n', "\r\n"));
+
+    write_out(&output)
+}
+
+fn write_out(text: &str) -> io::Result<()> {
+    let stdout = STDOUT.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut stdout = stdout.borrow_mut();
+    stdout.write_all(text.as_bytes())?;
+    stdout.flush()
+}
+
+/// The width of the terminal, or `COLUMNS` when it can't tell.
+fn columns() -> usize {
+    // SAFETY:
+    // winsize is plain data, so all zeros is a valid value, and the ioctl only fills it.
+    let mut size = unsafe { mem::zeroed::<libc::winsize>() };
+    let result = unsafe { libc::ioctl(terminal().as_raw_fd(), libc::TIOCGWINSZ, &mut size) };
+    if result == 0 && size.ws_col > 0 {
+        return size.ws_col as usize;
+    }
+
+    get_var("COLUMNS")
+        .and_then(|columns| columns.parse::<usize>().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(80)
+}
+
+/// How many columns the text takes in the terminal. The escape sequences, as the colors, and
+/// what the prompt marks with `\[` and `\]` take none.
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            START_INVISIBLE => {
+                chars.find(|&c| c == END_INVISIBLE);
+            }
+            '\x1b' => match chars.next() {
+                // CSI, up to the final byte
+                Some('[') => {
+                    chars.find(|c| ('\x40'..='\x7e').contains(c));
+                }
+                // OSC, as the title of the window, up to the bell or ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            c => width += char_width(c),
+        }
+    }
+
+    width
+}
+
+/// How many columns the char takes in the terminal: two for the wide ones, as the CJK and
+/// most emoji, none for the control chars and the ones that combine with the one before.
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// Where the text goes in rows of the columns, after a prefix that takes the width: the row
+/// and column after its last char, and the ones where the char at the position starts. After
+/// the last char a row can be full, the terminal only wraps when another char comes. A wide
+/// char that doesn't fit in the rest of a row goes to the next one.
+fn layout(
+    prefix_width: usize,
+    text: &[char],
+    position: usize,
+    columns: usize,
+) -> ((usize, usize), (usize, usize)) {
+    let mut end = match prefix_width {
+        0 => (0, 0),
+        width if width.is_multiple_of(columns) => (width / columns - 1, columns),
+        width => (width / columns, width % columns),
+    };
+    let mut at_position = None;
+
+    for (i, &c) in text.iter().enumerate() {
+        let width = char_width(c);
+        if width > 0 && end.1 + width > columns {
+            end = (end.0 + 1, 0);
+        }
+        if i == position {
+            at_position = Some(end);
+        }
+        end.1 += width;
+    }
+
+    let (row, column) = at_position.unwrap_or(end);
+    let start = match column >= columns {
+        true => (row + 1, 0),
+        false => (row, column),
+    };
+    (end, start)
+}
+
+/// The prompt as it's written, without the marks of what takes no room.
+fn visible(prompt: &str) -> String {
+    prompt.replace([START_INVISIBLE, END_INVISIBLE], "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn measures_the_chars_in_columns() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("🦀!"), 3);
+        // e and a combining acute accent
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("\x1b[1;32m$\x1b[0m "), 2);
+        assert_eq!(display_width("\u{1}\x1b]0;title\x07\u{2}日> "), 4);
+    }
+
+    #[test]
+    fn lays_the_text_out_in_rows() {
+        // the ends and the cursors
+        assert_eq!(layout(2, &chars("abc"), 3, 10), ((0, 5), (0, 5)));
+        assert_eq!(layout(2, &chars("abc"), 1, 10), ((0, 5), (0, 3)));
+        assert_eq!(layout(2, &chars("日本"), 1, 10), ((0, 6), (0, 4)));
+        assert_eq!(layout(2, &chars("e\u{301}x"), 2, 10), ((0, 4), (0, 3)));
+
+        // a full row wraps only when another char comes
+        assert_eq!(layout(2, &chars("abcdefgh"), 8, 10), ((0, 10), (1, 0)));
+        assert_eq!(layout(2, &chars("abcdefghi"), 9, 10), ((1, 1), (1, 1)));
+        assert_eq!(layout(10, &[], 0, 10), ((0, 10), (1, 0)));
+        assert_eq!(layout(20, &chars("a"), 0, 10), ((2, 1), (2, 0)));
+
+        // a wide char doesn't fit in the last column
+        assert_eq!(layout(2, &chars("abcdefg日"), 7, 10), ((1, 2), (1, 0)));
+        assert_eq!(layout(2, &chars("abcdefg日x"), 8, 10), ((1, 3), (1, 2)));
+        assert_eq!(layout(2, &chars("abcdef日"), 6, 10), ((0, 10), (0, 8)));
+    }
+}
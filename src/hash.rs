@@ -0,0 +1,494 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+
+use crate::{
+    interpreter::{environment::get_var, executor::add_exit_hook},
+    utils::POISONED_LOCK_MSG_ERR,
+};
+
+lazy_static! {
+    static ref CACHE: Mutex<RefCell<Cache>> = Mutex::new(RefCell::new(Cache::default()));
+}
+
+#[derive(Default)]
+struct Cache {
+    // what was found in every directory of PATH, kept between sessions in the cache file
+    directories: BTreeMap<PathBuf, Directory>,
+    // the commands already looked for, as `hash` shows them
+    hashed: BTreeMap<String, Hashed>,
+    // the PATH the commands were hashed with, another one may find them somewhere else
+    path: String,
+    // the directories changed since the cache file was read
+    unsaved: bool,
+}
+
+struct Directory {
+    // when it was last modified as it was read, none if it may still change in the same tick
+    modified: Option<Duration>,
+    executables: BTreeSet<String>,
+}
+
+struct Hashed {
+    path: PathBuf,
+    // how many times it was run
+    hits: usize,
+}
+
+impl Cache {
+    /// Commands hashed with another PATH may be somewhere else now, they are forgotten.
+    fn follow_path(&mut self) {
+        let path = get_var("PATH").unwrap_or_default();
+        if self.path != path {
+            self.hashed.clear();
+            self.path = path;
+        }
+    }
+
+    /// Reads again the directories of the PATH that changed since the last time, the others
+    /// are only looked at for their modification time.
+    fn refresh(&mut self) {
+        let path = self.path.clone();
+        for directory in env::split_paths(&path) {
+            let modified = fs::metadata(&directory)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+            let Some(modified) = modified else {
+                // maybe the directory in PATH doesn't exist
+                if self.directories.remove(&directory).is_some() {
+                    self.unsaved = true;
+                }
+                continue;
+            };
+            if self
+                .directories
+                .get(&directory)
+                .is_some_and(|cached| cached.modified == Some(modified))
+            {
+                continue;
+            }
+
+            let executables = executables_in(&directory);
+            let mut hashed = std::mem::take(&mut self.hashed);
+            let before = self
+                .directories
+                .get(&directory)
+                .map(|cached| &cached.executables);
+            // a new one may hide a hashed one further in PATH, or the hashed one may be gone
+            hashed.retain(|name, hashed| {
+                let added = executables.contains(name) && !before.is_some_and(|b| b.contains(name));
+                let removed =
+                    hashed.path.parent() == Some(&directory) && !executables.contains(name);
+                !added && !removed
+            });
+            self.hashed = hashed;
+
+            // Changes within the same tick would leave the time as it is, so a directory that
+            // changed right now is read again the next time
+            let settled = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .is_ok_and(|now| now.saturating_sub(modified) > Duration::from_secs(1));
+            self.directories.insert(
+                directory,
+                Directory {
+                    modified: settled.then_some(modified),
+                    executables,
+                },
+            );
+            self.unsaved = true;
+        }
+    }
+}
+
+/// Loads what the last sessions found in PATH, and saves it again when the shell exits.
+pub fn init() {
+    if let Some(path) = cache_file() {
+        let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+        cache.borrow_mut().directories = load(&path);
+    }
+    refresh();
+
+    add_exit_hook(|| {
+        if let Err(e) = save() {
+            eprintln!("hash: {e}");
+        }
+    });
+}
+
+/// Reads again the directories of PATH that changed since the last time, the others are only
+/// looked at for their modification time. Called before every prompt.
+pub fn refresh() {
+    let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut cache = cache.borrow_mut();
+
+    cache.follow_path();
+    cache.refresh();
+}
+
+/// Where the command is in PATH, remembering it for the next time. It counts as a hit.
+pub fn find(name: &str) -> Option<PathBuf> {
+    let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut cache = cache.borrow_mut();
+    cache.follow_path();
+
+    if let Some(hashed) = cache.hashed.get_mut(name) {
+        if is_executable(&hashed.path) {
+            hashed.hits += 1;
+            return Some(hashed.path.clone());
+        }
+        // removed since it was hashed, it may be somewhere else now
+        cache.hashed.remove(name);
+    }
+
+    let path = search(&cache.path, name).next()?;
+    cache.hashed.insert(
+        name.to_owned(),
+        Hashed {
+            path: path.clone(),
+            hits: 1,
+        },
+    );
+    Some(path)
+}
+
+/// Where the command was hashed, without counting it as a hit.
+pub fn get(name: &str) -> Option<PathBuf> {
+    let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+    let cache = cache.borrow();
+    cache.hashed.get(name).map(|hashed| hashed.path.clone())
+}
+
+/// Looks for the command in PATH and remembers where it is, without running it.
+pub fn remember(name: &str) -> Option<PathBuf> {
+    let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut cache = cache.borrow_mut();
+    cache.follow_path();
+
+    let path = search(&cache.path, name).next()?;
+    cache.hashed.insert(
+        name.to_owned(),
+        Hashed {
+            path: path.clone(),
+            hits: 0,
+        },
+    );
+    Some(path)
+}
+
+/// Tells where the command is, instead of looking for it in PATH, as `hash -p`.
+pub fn set(name: &str, path: &Path) {
+    let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+    cache.borrow_mut().hashed.insert(
+        name.to_owned(),
+        Hashed {
+            path: path.to_path_buf(),
+            hits: 0,
+        },
+    );
+}
+
+/// Forgets where the command is. Returns if it was hashed.
+pub fn forget(name: &str) -> bool {
+    let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+    cache.borrow_mut().hashed.remove(name).is_some()
+}
+
+pub fn forget_all() {
+    let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+    cache.borrow_mut().hashed.clear();
+}
+
+/// The hashed commands, with where they are and how many times they were run.
+pub fn hashed() -> Vec<(String, PathBuf, usize)> {
+    let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+    let cache = cache.borrow();
+    cache
+        .hashed
+        .iter()
+        .map(|(name, hashed)| (name.clone(), hashed.path.clone(), hashed.hits))
+        .collect()
+}
+
+/// The names of every executable in PATH.
+pub fn executable_names() -> BTreeSet<String> {
+    let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+    let cache = cache.borrow();
+    env::split_paths(&cache.path)
+        .filter_map(|directory| cache.directories.get(&directory))
+        .flat_map(|directory| directory.executables.iter().cloned())
+        .collect()
+}
+
+/// Every executable with the name in PATH, in the order of its directories, without
+/// remembering any of them.
+pub fn find_all(name: &str) -> Vec<PathBuf> {
+    search(&get_var("PATH").unwrap_or_default(), name).collect()
+}
+
+/// The executables with the name in the directories of the PATH, in its order. They are
+/// looked at as they are now, what was read of them may be old already and the first one must
+/// always win. An empty directory is the current one.
+fn search<'a>(path: &'a str, name: &'a str) -> impl Iterator<Item = PathBuf> + 'a {
+    // a name with a slash is already a path
+    let valid = !name.is_empty() && !name.contains('/');
+    env::split_paths(path)
+        .filter(move |_| valid)
+        .map(move |directory| directory.join(name))
+        .filter(|candidate| is_executable(candidate))
+}
+
+fn executables_in(directory: &Path) -> BTreeSet<String> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return BTreeSet::new();
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| is_executable(&entry.path()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        // a line of the cache file for each one
+        .filter(|name| !name.contains('\n'))
+        .collect()
+}
+
+/// A file anyone can execute, following the symbolic links.
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// The file the directories are saved to, ~/.tsh_sources.
+fn cache_file() -> Option<PathBuf> {
+    get_var("HOME").map(|home| PathBuf::from(home).join(".tsh_sources"))
+}
+
+/// Every directory starts with a `<seconds>.<nanoseconds> <directory>` line, followed by its
+/// executables, one per line, and an empty line.
+fn parse(content: &str) -> BTreeMap<PathBuf, Directory> {
+    let mut directories = BTreeMap::new();
+
+    for block in content.split("\n\n") {
+        let mut lines = block.lines();
+        let Some((modified, directory)) = lines.next().and_then(|line| line.split_once(' ')) else {
+            continue;
+        };
+        let Some((seconds, nanoseconds)) = modified.split_once('.') else {
+            continue;
+        };
+        let (Ok(seconds), Ok(nanoseconds)) = (seconds.parse(), nanoseconds.parse()) else {
+            continue;
+        };
+
+        directories.insert(
+            PathBuf::from(directory),
+            Directory {
+                modified: Some(Duration::new(seconds, nanoseconds)),
+                executables: lines.map(str::to_owned).collect(),
+            },
+        );
+    }
+
+    directories
+}
+
+/// What the cache file has, nothing if it can't be read.
+fn load(path: &Path) -> BTreeMap<PathBuf, Directory> {
+    fs::read_to_string(path)
+        .map(|content| parse(&content))
+        .unwrap_or_default()
+}
+
+/// The cache file of the directories, as `parse` reads it. The ones that may still change are
+/// left out, so the next session reads them again.
+fn format(directories: &BTreeMap<PathBuf, Directory>) -> String {
+    let mut content = String::new();
+    for (directory, cached) in directories.iter() {
+        let (Some(modified), Some(name)) = (cached.modified, directory.to_str()) else {
+            continue;
+        };
+        if name.contains('\n') {
+            continue;
+        }
+
+        content.push_str(&format!(
+            "{}.{} {name}\n",
+            modified.as_secs(),
+            modified.subsec_nanos()
+        ));
+        for executable in cached.executables.iter() {
+            content.push_str(executable);
+            content.push('\n');
+        }
+        content.push('\n');
+    }
+    content
+}
+
+/// Writes the directories over the cache file, if they changed. It's written aside and
+/// renamed, so other shells never read it half written.
+fn save() -> Result<()> {
+    let Some(path) = cache_file() else {
+        return Ok(());
+    };
+
+    let content = {
+        let cache = CACHE.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut cache = cache.borrow_mut();
+        if !cache.unsaved {
+            return Ok(());
+        }
+        cache.unsaved = false;
+
+        format(&cache.directories)
+    };
+
+    let temporary = path.with_extension(format!("{}", process::id()));
+    fs::write(&temporary, content)?;
+    fs::rename(&temporary, &path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    /// A directory of its own for the test, with the executables in it.
+    fn directory(name: &str, executables: &[&str]) -> PathBuf {
+        let directory = env::temp_dir().join(format!("tsh-hash-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        for executable in executables {
+            add_executable(&directory, executable);
+        }
+        directory
+    }
+
+    fn add_executable(directory: &Path, name: &str) {
+        let path = directory.join(name);
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    /// Sets when the directory was modified, some time ago so it counts as settled.
+    fn set_modified(directory: &Path, seconds_ago: u64) -> Duration {
+        let modified = SystemTime::now() - Duration::from_secs(seconds_ago);
+        File::open(directory)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        modified.duration_since(UNIX_EPOCH).unwrap()
+    }
+
+    fn cache_of(directory: &Path) -> Cache {
+        Cache {
+            path: directory.to_str().unwrap().to_owned(),
+            ..Cache::default()
+        }
+    }
+
+    fn executables(cache: &Cache, directory: &Path) -> Vec<String> {
+        cache.directories[directory]
+            .executables
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn reads_a_directory_again_when_its_time_changes() {
+        let directory = directory("changes", &["a"]);
+        let modified = set_modified(&directory, 60);
+        let mut cache = cache_of(&directory);
+
+        cache.refresh();
+        assert_eq!(executables(&cache, &directory), ["a"]);
+        assert_eq!(cache.directories[&directory].modified, Some(modified));
+        assert!(cache.unsaved);
+
+        // With the same time it's not read again
+        add_executable(&directory, "b");
+        let modified = set_modified(&directory, 60);
+        cache.directories.get_mut(&directory).unwrap().modified = Some(modified);
+        cache.unsaved = false;
+        cache.refresh();
+        assert_eq!(executables(&cache, &directory), ["a"]);
+        assert!(!cache.unsaved);
+
+        cache.hashed.insert(
+            String::from("a"),
+            Hashed {
+                path: directory.join("a"),
+                hits: 1,
+            },
+        );
+        fs::remove_file(directory.join("a")).unwrap();
+        set_modified(&directory, 30);
+        cache.refresh();
+        assert_eq!(executables(&cache, &directory), ["b"]);
+        assert!(cache.unsaved);
+        // the hashed one is gone with it
+        assert!(cache.hashed.is_empty());
+
+        // One that changed right now may change again in the same tick
+        add_executable(&directory, "c");
+        cache.refresh();
+        assert_eq!(executables(&cache, &directory), ["b", "c"]);
+        assert_eq!(cache.directories[&directory].modified, None);
+
+        fs::remove_dir_all(&directory).unwrap();
+        cache.refresh();
+        assert!(cache.directories.is_empty());
+    }
+
+    #[test]
+    fn starts_over_without_a_good_cache_file() {
+        let directory = directory("file", &["a"]);
+        let modified = set_modified(&directory, 60);
+        // out of the directory, or writing it would change its time
+        let file = directory.with_extension("tsh_sources");
+
+        assert!(load(&file).is_empty());
+
+        fs::write(&file, [0xff, 0xfe, b'\n']).unwrap();
+        assert!(load(&file).is_empty());
+
+        // Only what can be read is kept, and a directory with a wrong time is read again
+        fs::write(
+            &file,
+            format!(
+                "garbage\n\n1.x /bin\nls\n\n12 /usr/bin\ncat\n\n1.0 {}\nghost\n",
+                directory.display()
+            ),
+        )
+        .unwrap();
+        let mut cache = cache_of(&directory);
+        cache.directories = load(&file);
+        assert_eq!(cache.directories.keys().collect::<Vec<_>>(), [&directory]);
+        assert_eq!(executables(&cache, &directory), ["ghost"]);
+
+        cache.refresh();
+        assert_eq!(executables(&cache, &directory), ["a"]);
+
+        // What is saved is read back the same
+        fs::write(&file, format(&cache.directories)).unwrap();
+        let loaded = load(&file);
+        assert_eq!(loaded[&directory].modified, Some(modified));
+        assert_eq!(loaded[&directory].executables, ["a".to_owned()].into());
+
+        fs::remove_file(&file).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
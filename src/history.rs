@@ -0,0 +1,484 @@
+use std::{
+    cell::RefCell,
+    ffi::CString,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    mem,
+    path::PathBuf,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+use nix::{
+    fcntl::{Flock, FlockArg},
+    libc,
+};
+
+use crate::{
+    interpreter::{environment::get_var, executor::add_exit_hook},
+    utils::POISONED_LOCK_MSG_ERR,
+};
+
+lazy_static! {
+    static ref HISTORY: Mutex<RefCell<History>> = Mutex::new(RefCell::new(History::default()));
+}
+
+// Only the interactive shell keeps a history, scripts run without it
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const DEFAULT_SIZE: usize = 500;
+const DEFAULT_FILE_SIZE: usize = 1000;
+
+#[derive(Clone)]
+pub struct Entry {
+    // seconds since the epoch, 0 when unknown
+    pub timestamp: u64,
+    pub line: String,
+}
+
+#[derive(Default)]
+struct History {
+    entries: Vec<Entry>,
+    // the last entries, the ones that still aren't in the file
+    unsaved: usize,
+}
+
+/// Loads the history file and starts recording the lines.
+pub fn init() {
+    ENABLED.store(true, Ordering::Relaxed);
+
+    let entries = history_file()
+        .and_then(|path| File::open(path).ok())
+        .and_then(|file| Flock::lock(file, FlockArg::LockShared).ok())
+        .map(|mut file| {
+            let mut content = String::new();
+            let _ = file.read_to_string(&mut content);
+            parse(&content)
+        })
+        .unwrap_or_default();
+
+    {
+        let history = HISTORY.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut history = history.borrow_mut();
+        history.entries = entries;
+        history.trim(max_size("HISTSIZE", DEFAULT_SIZE));
+    }
+
+    add_exit_hook(|| {
+        if let Err(e) = save() {
+            eprintln!("history: {e}");
+        }
+    });
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The file the history goes to: HISTFILE, or ~/.tsh_history by default.
+fn history_file() -> Option<PathBuf> {
+    match get_var("HISTFILE") {
+        Some(file) if file.is_empty() => None,
+        Some(file) => Some(PathBuf::from(file)),
+        None => get_var("HOME").map(|home| PathBuf::from(home).join(".tsh_history")),
+    }
+}
+
+fn max_size(variable: &str, default: usize) -> usize {
+    get_var(variable)
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Every entry starts with a `#<timestamp>` line followed by the lines of the command. Lines
+/// before any timestamp are entries of their own, as in a file written by someone else.
+fn parse(content: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = vec![];
+    let mut timestamp = None;
+
+    for line in content.lines() {
+        if let Some(seconds) = line.strip_prefix('#')
+            && let Ok(seconds) = seconds.parse()
+        {
+            timestamp = Some(seconds);
+            continue;
+        }
+
+        match (timestamp.take(), entries.last_mut()) {
+            (Some(timestamp), _) => entries.push(Entry {
+                timestamp,
+                line: line.to_owned(),
+            }),
+            // the rest of a command of many lines
+            (None, Some(last)) if last.timestamp != 0 => {
+                last.line.push('\n');
+                last.line.push_str(line);
+            }
+            (None, _) => entries.push(Entry {
+                timestamp: 0,
+                line: line.to_owned(),
+            }),
+        }
+    }
+
+    entries
+}
+
+fn format_entries(entries: &[Entry]) -> String {
+    entries
+        .iter()
+        .map(|entry| format!("#{}\n{}\n", entry.timestamp, entry.line))
+        .collect()
+}
+
+impl History {
+    fn trim(&mut self, size: usize) {
+        if self.entries.len() > size {
+            self.entries.drain(..self.entries.len() - size);
+            self.unsaved = self.unsaved.min(size);
+        }
+    }
+
+    fn add(&mut self, line: &str) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+
+        self.entries.push(Entry {
+            timestamp,
+            line: line.to_owned(),
+        });
+        self.unsaved += 1;
+        self.trim(max_size("HISTSIZE", DEFAULT_SIZE));
+    }
+
+    /// An entry by its number, as shown by `history`.
+    fn get(&self, number: usize) -> Option<&Entry> {
+        self.entries.get(number.checked_sub(1)?)
+    }
+
+    fn last(&self) -> Result<&Entry> {
+        self.entries
+            .last()
+            .ok_or_else(|| anyhow!("!!: event not found"))
+    }
+}
+
+/// Records a line that was executed.
+pub fn add(line: &str) {
+    let line = line.trim_end_matches('\n');
+    if !is_enabled() || line.trim().is_empty() {
+        return;
+    }
+
+    let history = HISTORY.lock().expect(POISONED_LOCK_MSG_ERR);
+    history.borrow_mut().add(line);
+}
+
+/// The lines of the history, from the oldest to the newest.
+pub fn lines() -> Vec<String> {
+    let history = HISTORY.lock().expect(POISONED_LOCK_MSG_ERR);
+    let history = history.borrow();
+    history
+        .entries
+        .iter()
+        .map(|entry| entry.line.clone())
+        .collect()
+}
+
+/// The entries with their numbers, the last `count` of them or all.
+pub fn list(count: Option<usize>) -> Vec<(usize, Entry)> {
+    let history = HISTORY.lock().expect(POISONED_LOCK_MSG_ERR);
+    let history = history.borrow();
+    let skip = count.map_or(0, |count| history.entries.len().saturating_sub(count));
+
+    history
+        .entries
+        .iter()
+        .cloned()
+        .enumerate()
+        .skip(skip)
+        .map(|(i, entry)| (i + 1, entry))
+        .collect()
+}
+
+pub fn clear() {
+    let history = HISTORY.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut history = history.borrow_mut();
+    history.entries.clear();
+    history.unsaved = 0;
+}
+
+pub fn delete(number: usize) -> Result<()> {
+    let history = HISTORY.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut history = history.borrow_mut();
+    if history.get(number).is_none() {
+        return Err(anyhow!("{number}: history position out of range"));
+    }
+
+    let saved = history.entries.len() - history.unsaved;
+    history.entries.remove(number - 1);
+    if number > saved {
+        history.unsaved -= 1;
+    }
+
+    Ok(())
+}
+
+/// Appends what is new to the history file. The file is locked meanwhile, so many shells
+/// exiting at once don't mix their lines, and trimmed to HISTFILESIZE entries.
+pub fn save() -> Result<()> {
+    if !is_enabled() {
+        return Ok(());
+    }
+    let Some(path) = history_file() else {
+        return Ok(());
+    };
+
+    let new = {
+        let history = HISTORY.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut history = history.borrow_mut();
+        let new = history.entries[history.entries.len() - history.unsaved..].to_vec();
+        history.unsaved = 0;
+        new
+    };
+
+    let file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(&path)?;
+    let mut file = Flock::lock(file, FlockArg::LockExclusive).map_err(|(_, e)| e)?;
+    file.write_all(format_entries(&new).as_bytes())?;
+
+    // Now with the lines of every shell, keep only the newest
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut content)?;
+    let mut entries = parse(&content);
+    let size = max_size("HISTFILESIZE", DEFAULT_FILE_SIZE);
+    if entries.len() > size {
+        entries.drain(..entries.len() - size);
+        file.set_len(0)?;
+        file.write_all(format_entries(&entries).as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Writes the whole history over the history file.
+pub fn write() -> Result<()> {
+    let Some(path) = history_file() else {
+        return Ok(());
+    };
+
+    let entries = {
+        let history = HISTORY.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut history = history.borrow_mut();
+        history.unsaved = 0;
+        history.entries.clone()
+    };
+
+    // Truncated only once locked, someone else may be reading it
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+    let mut file = Flock::lock(file, FlockArg::LockExclusive).map_err(|(_, e)| e)?;
+    file.set_len(0)?;
+    file.write_all(format_entries(&entries).as_bytes())?;
+
+    Ok(())
+}
+
+/// Formats the time of an entry with a strftime format, as HISTTIMEFORMAT.
+pub fn format_timestamp(timestamp: u64, format: &str) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let time = timestamp as libc::time_t;
+
+    // SAFETY:
+    // tm is plain data, so all zeros is a valid value, and localtime_r fills it from the time,
+    // both owned by this function.
+    let mut tm = unsafe { mem::zeroed::<libc::tm>() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+
+    let mut buffer = [0u8; 256];
+    // SAFETY:
+    // strftime writes at most the len of the buffer and returns how much it wrote.
+    let len = unsafe {
+        libc::strftime(
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+/// Replaces the history references of a line: `!!` the last command, `!n` the command
+/// number n, `!-n` the nth command back, `!prefix` the last command starting with the
+/// prefix, `!?text?` the last command containing the text, and a first line that is only
+/// `^old^new` the last command with old replaced by new. Single quotes and a backslash keep a
+/// `!` as it is, as does closing double quotes.
+pub fn expand(line: &str) -> Result<String> {
+    if !is_enabled() || !line.contains(['!', '^']) {
+        return Ok(line.to_owned());
+    }
+
+    let history = HISTORY.lock().expect(POISONED_LOCK_MSG_ERR);
+    let history = history.borrow();
+
+    // `^cmd` is also a command that doesn't wait, only a line that is `^old^new` and nothing
+    // else is a substitution
+    let (first, rest) = line.split_once('\n').unwrap_or((line, ""));
+    if let Some((old, new)) = quick_substitution(first) {
+        let last = &history.last()?.line;
+        if !last.contains(old) {
+            return Err(anyhow!("{first}: substitution failed"));
+        }
+
+        let mut expanded = last.replacen(old, new, 1);
+        if !rest.is_empty() {
+            expanded.push('\n');
+            expanded.push_str(rest);
+        }
+        return Ok(expanded);
+    }
+
+    let mut expanded = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    let mut single_quotes = false;
+    let mut double_quotes = false;
+    let mut previous = None;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if !double_quotes => single_quotes = !single_quotes,
+            '"' if !single_quotes => double_quotes = !double_quotes,
+            '\\' if !single_quotes => {
+                expanded.push(c);
+                if let Some(escaped) = chars.next() {
+                    expanded.push(escaped);
+                }
+                previous = Some('\\');
+                continue;
+            }
+            // `[!...]` is a pattern and `$!` a parameter
+            '!' if single_quotes || matches!(previous, Some('[') | Some('$')) => {}
+            '!' => {
+                let event = match chars.peek() {
+                    Some('!') => {
+                        chars.next();
+                        Some(history.last()?)
+                    }
+                    Some(&c) if c.is_ascii_digit() || c == '-' => {
+                        let mut number = String::new();
+                        if chars.next_if_eq(&'-').is_some() {
+                            number.push('-');
+                        }
+                        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                            number.push(digit);
+                        }
+
+                        let entry = match number.parse::<isize>() {
+                            Ok(back) if back < 0 => history
+                                .entries
+                                .len()
+                                .checked_sub(back.unsigned_abs())
+                                .and_then(|i| history.entries.get(i)),
+                            Ok(number) => history.get(number as usize),
+                            Err(_) => None,
+                        };
+                        Some(entry.ok_or_else(|| anyhow!("!{number}: event not found"))?)
+                    }
+                    Some('?') => {
+                        chars.next();
+                        let text = chars.by_ref().take_while(|&c| c != '?').collect::<String>();
+                        let entry = history
+                            .entries
+                            .iter()
+                            .rev()
+                            .find(|entry| entry.line.contains(&text));
+                        Some(entry.ok_or_else(|| anyhow!("!?{text}: event not found"))?)
+                    }
+                    // a lonely ! is just a !, like `! cmd`, `!=` or `"hi!"`
+                    None | Some(' ') | Some('\t') | Some('\n') | Some('=') | Some('(') => None,
+                    Some('"') if double_quotes => None,
+                    Some(_) => {
+                        let mut prefix = String::new();
+                        while let Some(c) = chars.next_if(|&c| {
+                            !c.is_whitespace() && !matches!(c, ';' | '|' | '&' | '<' | '>' | '"')
+                        }) {
+                            prefix.push(c);
+                        }
+                        let entry = history
+                            .entries
+                            .iter()
+                            .rev()
+                            .find(|entry| entry.line.starts_with(&prefix));
+                        Some(entry.ok_or_else(|| anyhow!("!{prefix}: event not found"))?)
+                    }
+                };
+
+                if let Some(event) = event {
+                    expanded.push_str(&event.line);
+                    previous = event.line.chars().last();
+                    continue;
+                }
+            }
+            _ => {}
+        }
+
+        expanded.push(c);
+        previous = Some(c);
+    }
+
+    Ok(expanded)
+}
+
+/// What `^old^new` or `^old^new^` replaces. The old text can't have blanks, so `^cmd arg ^x`
+/// is still a command.
+fn quick_substitution(line: &str) -> Option<(&str, &str)> {
+    let substitution = line.strip_prefix('^')?;
+    let (old, new) = substitution.split_once('^')?;
+    let new = new.strip_suffix('^').unwrap_or(new);
+
+    if old.is_empty() || old.contains(char::is_whitespace) || new.contains('^') {
+        return None;
+    }
+    Some((old, new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_the_references() {
+        ENABLED.store(true, Ordering::Relaxed);
+        add("echo foo bar");
+
+        assert_eq!(expand("!!").unwrap(), "echo foo bar");
+        assert_eq!(expand("^foo^baz").unwrap(), "echo baz bar");
+        assert_eq!(expand("^foo^baz^").unwrap(), "echo baz bar");
+
+        // a ! closing the quotes is just a !
+        assert_eq!(expand("echo \"hi!\"").unwrap(), "echo \"hi!\"");
+        assert_eq!(expand("echo \"!!\"").unwrap(), "echo \"echo foo bar\"");
+        assert_eq!(expand("echo '!!' x != y").unwrap(), "echo '!!' x != y");
+
+        // a command that doesn't wait, with a ^ in its args
+        assert_eq!(expand("^sleep 1 ^x").unwrap(), "^sleep 1 ^x");
+        assert_eq!(expand("^sleep 1").unwrap(), "^sleep 1");
+    }
+}
@@ -0,0 +1,487 @@
+use anyhow::{Result, anyhow};
+
+use crate::interpreter::environment::{get_var, is_valid_name, set_var};
+
+// A variable holding an expression holding a variable... stops somewhere
+const MAX_DEPTH: usize = 64;
+
+/// Evaluates an integer expression with the operators of C, as `$((...))`, `((...))` and
+/// `let` do. The variables are read and assigned by name, an unset or empty one is 0.
+pub fn evaluate(expression: &str) -> Result<i64> {
+    evaluate_nested(expression, 0)
+}
+
+fn evaluate_nested(expression: &str, depth: usize) -> Result<i64> {
+    if depth > MAX_DEPTH {
+        return Err(anyhow!("{expression}: expression recursion level exceeded"));
+    }
+
+    let value = tokenize(expression).and_then(|tokens| {
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            skipping: false,
+            depth,
+        };
+
+        match parser.peek() {
+            None => Ok(0),
+            Some(_) => {
+                let value = parser.comma()?;
+                match parser.peek() {
+                    None => Ok(value),
+                    Some(_) => Err(parser.unexpected()),
+                }
+            }
+        }
+    });
+
+    value.map_err(|e| anyhow!("{expression}: {e}"))
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(number) => write!(f, "{number}"),
+            Self::Name(name) => write!(f, "{name}"),
+            Self::Operator(operator) => write!(f, "{operator}"),
+        }
+    }
+}
+
+// The longest ones first, so `<<=` is not taken as `<` and `<=`
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "^=", "|=", "<<", ">>",
+    "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^",
+    "|", "?", ":", ",", "(", ")",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = expression.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '#' && c != '@' && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(parse_number(&rest[..len])?));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_owned()));
+            len
+        } else if let Some(operator) = OPERATORS.iter().find(|&&op| rest.starts_with(op)) {
+            tokens.push(Token::Operator(operator));
+            operator.len()
+        } else {
+            return Err(anyhow!(
+                "syntax error: operand expected (error token is \"{rest}\")"
+            ));
+        };
+
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// `255`, `0xff`, `0377` or `16#ff`. In a base up to 36 the letters are the same in upper
+/// or lower case, above it the upper case ones come after, then `@` and `_`.
+fn parse_number(text: &str) -> Result<i64> {
+    let invalid = || anyhow!("value too great for base (error token is \"{text}\")");
+
+    let (base, digits) = match text.split_once('#') {
+        Some((base, digits)) => {
+            let base = base.parse::<u32>().map_err(|_| invalid())?;
+            if !(2..=64).contains(&base) {
+                return Err(anyhow!(
+                    "invalid arithmetic base (error token is \"{text}\")"
+                ));
+            }
+            (base, digits)
+        }
+        None => match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(digits) => (16, digits),
+            None if text.len() > 1 && text.starts_with('0') => (8, &text[1..]),
+            None => (10, text),
+        },
+    };
+
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut value = 0i64;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(invalid()),
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+
+    Ok(value)
+}
+
+/// The binary operators from the lowest precedence to the highest, all left associative.
+const BINARY_LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    // the side of a `&&`, `||` or `?:` that doesn't count, parsed but never evaluated
+    skipping: bool,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next_if_operator(&mut self, operators: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                let operator = *operator;
+                self.position += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<()> {
+        match self.next_if_operator(&[operator]) {
+            Some(_) => Ok(()),
+            None => Err(self.unexpected()),
+        }
+    }
+
+    fn unexpected(&self) -> anyhow::Error {
+        match self.peek() {
+            Some(token) => anyhow!("syntax error in expression (error token is \"{token}\")"),
+            None => anyhow!("syntax error: operand expected"),
+        }
+    }
+
+    /// `a, b`, the value is the last one.
+    fn comma(&mut self) -> Result<i64> {
+        let mut value = self.assignment()?;
+        while self.next_if_operator(&[","]).is_some() {
+            value = self.assignment()?;
+        }
+
+        Ok(value)
+    }
+
+    fn assignment(&mut self) -> Result<i64> {
+        const ASSIGNMENTS: [&str; 11] = [
+            "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|=",
+        ];
+
+        if let Some(Token::Name(name)) = self.peek()
+            && let Some(Token::Operator(operator)) = self.tokens.get(self.position + 1)
+            && ASSIGNMENTS.contains(operator)
+        {
+            let (name, operator) = (name.clone(), *operator);
+            self.position += 2;
+
+            let right = self.assignment()?;
+            let value = match operator.strip_suffix('=').unwrap_or_default() {
+                "" => right,
+                operator => apply(operator, self.variable(&name)?, right, self.skipping)?,
+            };
+            return self.assign(&name, value);
+        }
+
+        self.ternary()
+    }
+
+    fn ternary(&mut self) -> Result<i64> {
+        let condition = self.binary(0)?;
+        if self.next_if_operator(&["?"]).is_none() {
+            return Ok(condition);
+        }
+
+        let skipping = self.skipping;
+        self.skipping = skipping || condition == 0;
+        let then = self.comma()?;
+        self.expect(":")?;
+        self.skipping = skipping || condition != 0;
+        let otherwise = self.assignment()?;
+        self.skipping = skipping;
+
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64> {
+        let Some(operators) = BINARY_LEVELS.get(level) else {
+            return self.power();
+        };
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(operator) = self.next_if_operator(operators) {
+            // the right side of && and || only counts when the left one doesn't decide
+            let skipping = self.skipping;
+            match operator {
+                "&&" => self.skipping |= left == 0,
+                "||" => self.skipping |= left != 0,
+                _ => {}
+            }
+            let right = self.binary(level + 1)?;
+            self.skipping = skipping;
+
+            left = apply(operator, left, right, self.skipping)?;
+        }
+
+        Ok(left)
+    }
+
+    /// `**` binds tighter than anything binary, and is right associative.
+    fn power(&mut self) -> Result<i64> {
+        let base = self.unary()?;
+        if self.next_if_operator(&["**"]).is_none() {
+            return Ok(base);
+        }
+
+        let exponent = self.power()?;
+        apply("**", base, exponent, self.skipping)
+    }
+
+    fn unary(&mut self) -> Result<i64> {
+        if let Some(Token::Operator(operator @ ("++" | "--"))) = self.peek().cloned()
+            && let Some(Token::Name(name)) = self.tokens.get(self.position + 1).cloned()
+        {
+            self.position += 2;
+
+            let step = if operator == "++" { 1 } else { -1 };
+            let value = self.variable(&name)?.wrapping_add(step);
+            return self.assign(&name, value);
+        }
+
+        match self.next_if_operator(&["+", "-", "!", "~", "++", "--"]) {
+            // without a variable after them, twice the sign
+            Some("+" | "++" | "--") => self.unary(),
+            Some("-") => Ok(self.unary()?.wrapping_neg()),
+            Some("!") => Ok(i64::from(self.unary()? == 0)),
+            Some(_) => Ok(!self.unary()?),
+            None => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<i64> {
+        if let Some(Token::Name(name)) = self.peek().cloned()
+            && let Some(Token::Operator(operator @ ("++" | "--"))) =
+                self.tokens.get(self.position + 1).cloned()
+        {
+            self.position += 2;
+
+            let value = self.variable(&name)?;
+            let step = if operator == "++" { 1 } else { -1 };
+            self.assign(&name, value.wrapping_add(step))?;
+            return Ok(value);
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<i64> {
+        match self.peek().cloned() {
+            Some(Token::Number(number)) => {
+                self.position += 1;
+                Ok(number)
+            }
+            Some(Token::Name(name)) => {
+                self.position += 1;
+                self.variable(&name)
+            }
+            Some(Token::Operator("(")) => {
+                self.position += 1;
+                let value = self.comma()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// The value of a variable, which can be an expression itself.
+    fn variable(&self, name: &str) -> Result<i64> {
+        if self.skipping {
+            return Ok(0);
+        }
+
+        match get_var(name) {
+            Some(value) if !value.trim().is_empty() => evaluate_nested(&value, self.depth + 1),
+            _ => Ok(0),
+        }
+    }
+
+    fn assign(&self, name: &str, value: i64) -> Result<i64> {
+        if !self.skipping {
+            if !is_valid_name(name) {
+                return Err(anyhow!("{name}: not a valid identifier"));
+            }
+            set_var(name, &value.to_string());
+        }
+
+        Ok(value)
+    }
+}
+
+fn apply(operator: &str, left: i64, right: i64, skipping: bool) -> Result<i64> {
+    Ok(match operator {
+        "||" => i64::from(left != 0 || right != 0),
+        "&&" => i64::from(left != 0 && right != 0),
+        "|" => left | right,
+        "^" => left ^ right,
+        "&" => left & right,
+        "==" => i64::from(left == right),
+        "!=" => i64::from(left != right),
+        "<" => i64::from(left < right),
+        "<=" => i64::from(left <= right),
+        ">" => i64::from(left > right),
+        ">=" => i64::from(left >= right),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        // what is never evaluated can't fail
+        "/" | "%" if right == 0 && skipping => 0,
+        "/" | "%" if right == 0 => return Err(anyhow!("division by 0")),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 && !skipping => return Err(anyhow!("exponent less than 0")),
+        "**" => left.wrapping_pow(right.clamp(0, u32::MAX as i64) as u32),
+        _ => unreachable!("{operator} is not a binary operator"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(expression: &str) -> String {
+        evaluate(expression).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn reads_the_numbers_in_every_base() {
+        assert_eq!(evaluate("255").unwrap(), 255);
+        assert_eq!(evaluate("0xff + 0XFF").unwrap(), 510);
+        assert_eq!(evaluate("0377").unwrap(), 255);
+        assert_eq!(evaluate("16#ff").unwrap(), 255);
+        assert_eq!(evaluate("2#1010").unwrap(), 10);
+        assert_eq!(evaluate("36#Z").unwrap(), 35);
+        assert_eq!(evaluate("64#Z").unwrap(), 61);
+        assert_eq!(evaluate("64#@ + 64#_").unwrap(), 125);
+
+        assert!(error("08").contains("value too great for base"));
+        assert!(error("2#12").contains("value too great for base"));
+        assert!(error("65#1").contains("invalid arithmetic base"));
+        assert!(error("1 $ 2").contains("operand expected"));
+    }
+
+    #[test]
+    fn follows_the_precedence_of_c() {
+        assert_eq!(evaluate("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(evaluate("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(evaluate("10 - 4 - 3").unwrap(), 3);
+        assert_eq!(evaluate("2 ** 3 ** 2").unwrap(), 512);
+        assert_eq!(evaluate("-2 ** 2").unwrap(), 4);
+        assert_eq!(evaluate("1 << 4 | 1").unwrap(), 17);
+        assert_eq!(evaluate("5 & 3 ^ 1").unwrap(), 0);
+        assert_eq!(evaluate("1 < 2 == 1").unwrap(), 1);
+        assert_eq!(evaluate("!0 + ~0").unwrap(), 0);
+        assert_eq!(evaluate("1 ? 2 : 3").unwrap(), 2);
+        assert_eq!(evaluate("0 ? 2 : 0 ? 3 : 4").unwrap(), 4);
+        assert_eq!(evaluate("1, 2, 3").unwrap(), 3);
+        assert_eq!(evaluate("").unwrap(), 0);
+        assert_eq!(evaluate("  ").unwrap(), 0);
+
+        assert!(evaluate("(1 + 2").is_err());
+        assert!(evaluate("1 2").is_err());
+    }
+
+    #[test]
+    fn fails_on_what_has_no_value() {
+        assert!(error("1 / 0").contains("division by 0"));
+        assert!(error("1 % 0").contains("division by 0"));
+        assert!(error("2 ** -1").contains("exponent less than 0"));
+        assert_eq!(evaluate("7 / -2").unwrap(), -3);
+        assert_eq!(evaluate("-7 % 2").unwrap(), -1);
+    }
+
+    #[test]
+    fn wraps_around_on_overflow() {
+        assert_eq!(evaluate("9223372036854775807 + 1").unwrap(), i64::MIN);
+        assert_eq!(evaluate("-9223372036854775807 - 2").unwrap(), i64::MAX);
+        assert_eq!(evaluate("-9223372036854775808 / -1").unwrap(), i64::MIN);
+        assert_eq!(evaluate("2 ** 64").unwrap(), 0);
+        assert_eq!(evaluate("1 << 64").unwrap(), 1);
+    }
+
+    #[test]
+    fn skips_what_is_not_evaluated() {
+        set_var("ARITH_TEST_SKIPPED", "0");
+
+        assert_eq!(evaluate("0 && (ARITH_TEST_SKIPPED = 1)").unwrap(), 0);
+        assert_eq!(evaluate("1 || ARITH_TEST_SKIPPED++").unwrap(), 1);
+        assert_eq!(evaluate("1 ? 2 : ARITH_TEST_SKIPPED += 5").unwrap(), 2);
+        assert_eq!(evaluate("0 && 1 / 0").unwrap(), 0);
+        assert_eq!(evaluate("1 ? 1 : 2 ** -1").unwrap(), 1);
+        assert_eq!(get_var("ARITH_TEST_SKIPPED").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn reads_and_assigns_the_variables() {
+        set_var("ARITH_TEST_X", "5");
+        set_var("ARITH_TEST_EXPRESSION", "ARITH_TEST_X * 2");
+
+        assert_eq!(evaluate("ARITH_TEST_UNSET + 1").unwrap(), 1);
+        assert_eq!(evaluate("ARITH_TEST_EXPRESSION + 1").unwrap(), 11);
+        assert_eq!(evaluate("ARITH_TEST_X++").unwrap(), 5);
+        assert_eq!(evaluate("++ARITH_TEST_X").unwrap(), 7);
+        assert_eq!(evaluate("ARITH_TEST_X--").unwrap(), 7);
+        assert_eq!(evaluate("ARITH_TEST_X <<= 2").unwrap(), 24);
+        assert_eq!(evaluate("ARITH_TEST_X %= 5").unwrap(), 4);
+        assert_eq!(evaluate("ARITH_TEST_Y = ARITH_TEST_Z = 3").unwrap(), 3);
+        assert_eq!(get_var("ARITH_TEST_X").as_deref(), Some("4"));
+        assert_eq!(get_var("ARITH_TEST_Y").as_deref(), Some("3"));
+        assert_eq!(get_var("ARITH_TEST_Z").as_deref(), Some("3"));
+
+        set_var("ARITH_TEST_LOOP", "ARITH_TEST_LOOP + 1");
+        assert!(error("ARITH_TEST_LOOP").contains("expression recursion level exceeded"));
+        assert!(evaluate("1 = 2").is_err());
+    }
+}
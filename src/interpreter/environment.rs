@@ -0,0 +1,362 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    env,
+    ffi::CString,
+    process,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicI32, AtomicU32, Ordering},
+    },
+};
+
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+
+use crate::{interpreter::parser::Command, utils::POISONED_LOCK_MSG_ERR};
+
+lazy_static! {
+    // The environment is owned by the shell, it starts as a copy of the environment of the
+    // process and from there on only the builtins change it. That's what the external
+    // commands receive on execve.
+    pub static ref ENVIRONMENT: Mutex<RefCell<BTreeMap<String, String>>> = Mutex::new(RefCell::new(
+        env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect()
+    ));
+    // The variables of the shell that are not exported, so the external commands never see
+    // them. A name lives in only one of the tables at a time.
+    pub static ref VARIABLES: Mutex<RefCell<BTreeMap<String, String>>> =
+        Mutex::new(RefCell::new(BTreeMap::new()));
+    // $0, the name of the shell or of the script it runs
+    static ref SHELL_NAME: Mutex<RefCell<String>> = Mutex::new(RefCell::new(
+        env::args().next().unwrap_or_else(|| String::from("tsh"))
+    ));
+    // $1, $2... the arguments of the script
+    static ref POSITIONAL_PARAMS: Mutex<RefCell<Vec<String>>> = Mutex::new(RefCell::new(vec![]));
+    // The options turned on by `set -o`
+    static ref OPTIONS: Mutex<RefCell<BTreeSet<&'static str>>> =
+        Mutex::new(RefCell::new(BTreeSet::from(["emacs"])));
+    // The functions defined so far, by name. A call keeps its own reference to the body, so
+    // the function can be defined again while it runs.
+    static ref FUNCTIONS: Mutex<RefCell<BTreeMap<String, Arc<Command>>>> =
+        Mutex::new(RefCell::new(BTreeMap::new()));
+    // What the names of commands given to `alias` stand for
+    static ref ALIASES: Mutex<RefCell<BTreeMap<String, String>>> =
+        Mutex::new(RefCell::new(BTreeMap::new()));
+    // One scope for each function running, with the variables it made local and what they
+    // were before, to put them back when it returns
+    static ref LOCAL_SCOPES: Mutex<RefCell<Vec<LocalScope>>> =
+        Mutex::new(RefCell::new(vec![]));
+}
+
+// the value of a variable and if it was exported, if it existed at all
+type SavedVar = Option<(String, bool)>;
+type LocalScope = Vec<(String, SavedVar)>;
+
+/// Every option `set -o` knows about.
+pub const OPTION_NAMES: &[&str] = &[
+    "dotglob",
+    "emacs",
+    "failglob",
+    "ignoreeof",
+    "nullglob",
+    "vi",
+];
+
+// The status of the last command that ran, the `$?`
+static LAST_STATUS: AtomicI32 = AtomicI32::new(0);
+// 0 until the first background job starts
+static LAST_BACKGROUND_PID: AtomicI32 = AtomicI32::new(0);
+// The `$$`, the children forked by the shell keep the one of the shell
+static SHELL_PID: AtomicU32 = AtomicU32::new(0);
+
+pub fn get_last_status() -> i32 {
+    LAST_STATUS.load(Ordering::Relaxed)
+}
+
+pub fn set_last_status(status: i32) {
+    LAST_STATUS.store(status, Ordering::Relaxed);
+}
+
+pub fn get_last_background_pid() -> Option<i32> {
+    match LAST_BACKGROUND_PID.load(Ordering::Relaxed) {
+        0 => None,
+        pid => Some(pid),
+    }
+}
+
+pub fn set_last_background_pid(pid: i32) {
+    LAST_BACKGROUND_PID.store(pid, Ordering::Relaxed);
+}
+
+/// Remembers the pid of the shell, it must be called before any child is forked.
+pub fn init_shell_pid() {
+    SHELL_PID.store(process::id(), Ordering::Relaxed);
+}
+
+pub fn get_shell_pid() -> u32 {
+    SHELL_PID.load(Ordering::Relaxed)
+}
+
+pub fn get_shell_name() -> String {
+    let name = SHELL_NAME.lock().expect(POISONED_LOCK_MSG_ERR);
+    name.borrow().clone()
+}
+
+pub fn set_shell_name(name: &str) {
+    let shell_name = SHELL_NAME.lock().expect(POISONED_LOCK_MSG_ERR);
+    *shell_name.borrow_mut() = name.to_owned();
+}
+
+pub fn get_positional_params() -> Vec<String> {
+    let params = POSITIONAL_PARAMS.lock().expect(POISONED_LOCK_MSG_ERR);
+    params.borrow().clone()
+}
+
+/// Replaces the positional parameters, returning the previous ones.
+pub fn set_positional_params(params: Vec<String>) -> Vec<String> {
+    let old = POSITIONAL_PARAMS.lock().expect(POISONED_LOCK_MSG_ERR);
+    old.replace(params)
+}
+
+pub fn is_option_set(name: &str) -> bool {
+    let options = OPTIONS.lock().expect(POISONED_LOCK_MSG_ERR);
+    options.borrow().contains(name)
+}
+
+pub fn set_option(name: &str, on: bool) -> Result<()> {
+    let Some(name) = OPTION_NAMES.iter().find(|option| **option == name) else {
+        return Err(anyhow!("{name}: invalid option name"));
+    };
+
+    let options = OPTIONS.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut options = options.borrow_mut();
+    if on {
+        // a single editing mode at a time
+        match *name {
+            "emacs" => options.remove("vi"),
+            "vi" => options.remove("emacs"),
+            _ => false,
+        };
+        options.insert(name);
+    } else {
+        options.remove(name);
+    }
+
+    Ok(())
+}
+
+pub fn get_var(name: &str) -> Option<String> {
+    {
+        let variables = VARIABLES.lock().expect(POISONED_LOCK_MSG_ERR);
+        let variables = variables.borrow();
+        if let Some(value) = variables.get(name) {
+            return Some(value.clone());
+        }
+    }
+
+    let environment = ENVIRONMENT.lock().expect(POISONED_LOCK_MSG_ERR);
+    let environment = environment.borrow();
+    environment.get(name).cloned()
+}
+
+/// The names of every variable, exported or not, sorted.
+pub fn get_var_names() -> Vec<String> {
+    let mut names = {
+        let environment = ENVIRONMENT.lock().expect(POISONED_LOCK_MSG_ERR);
+        environment
+            .borrow()
+            .keys()
+            .cloned()
+            .collect::<BTreeSet<_>>()
+    };
+
+    let variables = VARIABLES.lock().expect(POISONED_LOCK_MSG_ERR);
+    names.extend(variables.borrow().keys().cloned());
+    names.into_iter().collect()
+}
+
+/// Sets the value of a variable, keeping it exported if it already was.
+pub fn set_var(name: &str, value: &str) {
+    {
+        let environment = ENVIRONMENT.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut environment = environment.borrow_mut();
+        if let Some(exported) = environment.get_mut(name) {
+            *exported = value.to_owned();
+            return;
+        }
+    }
+
+    let variables = VARIABLES.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut variables = variables.borrow_mut();
+    variables.insert(name.to_owned(), value.to_owned());
+}
+
+/// Moves a variable to the environment, with an empty value if it doesn't exist yet.
+pub fn export_var(name: &str) {
+    let value = {
+        let variables = VARIABLES.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut variables = variables.borrow_mut();
+        variables.remove(name)
+    };
+
+    let environment = ENVIRONMENT.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut environment = environment.borrow_mut();
+    match value {
+        Some(value) => {
+            environment.insert(name.to_owned(), value);
+        }
+        None => {
+            environment.entry(name.to_owned()).or_default();
+        }
+    }
+}
+
+pub fn unset_var(name: &str) {
+    {
+        let variables = VARIABLES.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut variables = variables.borrow_mut();
+        variables.remove(name);
+    }
+
+    let environment = ENVIRONMENT.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut environment = environment.borrow_mut();
+    environment.remove(name);
+}
+
+pub fn get_function(name: &str) -> Option<Arc<Command>> {
+    let functions = FUNCTIONS.lock().expect(POISONED_LOCK_MSG_ERR);
+    functions.borrow().get(name).cloned()
+}
+
+pub fn set_function(name: &str, body: Arc<Command>) {
+    let functions = FUNCTIONS.lock().expect(POISONED_LOCK_MSG_ERR);
+    functions.borrow_mut().insert(name.to_owned(), body);
+}
+
+pub fn unset_function(name: &str) {
+    let functions = FUNCTIONS.lock().expect(POISONED_LOCK_MSG_ERR);
+    functions.borrow_mut().remove(name);
+}
+
+pub fn get_alias(name: &str) -> Option<String> {
+    let aliases = ALIASES.lock().expect(POISONED_LOCK_MSG_ERR);
+    aliases.borrow().get(name).cloned()
+}
+
+/// Every alias with its value, sorted by name.
+pub fn get_aliases() -> Vec<(String, String)> {
+    let aliases = ALIASES.lock().expect(POISONED_LOCK_MSG_ERR);
+    aliases
+        .borrow()
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+pub fn set_alias(name: &str, value: &str) {
+    let aliases = ALIASES.lock().expect(POISONED_LOCK_MSG_ERR);
+    aliases
+        .borrow_mut()
+        .insert(name.to_owned(), value.to_owned());
+}
+
+/// Forgets an alias, returns if there was any.
+pub fn unset_alias(name: &str) -> bool {
+    let aliases = ALIASES.lock().expect(POISONED_LOCK_MSG_ERR);
+    aliases.borrow_mut().remove(name).is_some()
+}
+
+/// Forgets every alias.
+pub fn clear_aliases() {
+    let aliases = ALIASES.lock().expect(POISONED_LOCK_MSG_ERR);
+    aliases.borrow_mut().clear();
+}
+
+/// Starts the scope of the local variables of a function that is about to run.
+pub fn push_local_scope() {
+    let scopes = LOCAL_SCOPES.lock().expect(POISONED_LOCK_MSG_ERR);
+    scopes.borrow_mut().push(vec![]);
+}
+
+/// Ends the scope of the function that returned, the variables it made local get back the
+/// values they had before.
+pub fn pop_local_scope() {
+    let scope = {
+        let scopes = LOCAL_SCOPES.lock().expect(POISONED_LOCK_MSG_ERR);
+        scopes.borrow_mut().pop().unwrap_or_default()
+    };
+
+    for (name, saved) in scope.into_iter().rev() {
+        unset_var(&name);
+        if let Some((value, exported)) = saved {
+            set_var(&name, &value);
+            if exported {
+                export_var(&name);
+            }
+        }
+    }
+}
+
+/// Makes a variable local to the function running, unset until it's given a value. It's
+/// seen by the functions it calls too, and goes back to what it was when the function returns.
+pub fn make_local(name: &str) -> Result<()> {
+    let exported = {
+        let environment = ENVIRONMENT.lock().expect(POISONED_LOCK_MSG_ERR);
+        environment.borrow().get(name).cloned()
+    };
+    let saved = match exported {
+        Some(value) => Some((value, true)),
+        None => {
+            let variables = VARIABLES.lock().expect(POISONED_LOCK_MSG_ERR);
+            let value = variables.borrow().get(name).cloned();
+            value.map(|value| (value, false))
+        }
+    };
+
+    {
+        let scopes = LOCAL_SCOPES.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut scopes = scopes.borrow_mut();
+        let Some(scope) = scopes.last_mut() else {
+            return Err(anyhow!("can only be used in a function"));
+        };
+        // local twice in the same function, the value to put back is the first one
+        if scope.iter().any(|(local, _)| local == name) {
+            return Ok(());
+        }
+        scope.push((name.to_owned(), saved));
+    }
+
+    unset_var(name);
+    Ok(())
+}
+
+/// Builds the `NAME=value` list that execve expects, with the assignments taking precedence
+/// over what is in the environment.
+pub fn exec_environment(assignments: &[(String, String)]) -> Vec<CString> {
+    let environment = ENVIRONMENT.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut environment = environment.borrow().clone();
+    environment.extend(assignments.iter().cloned());
+
+    environment
+        .into_iter()
+        // a \0 can't be passed through execve, so just leave it out
+        .filter_map(|(name, value)| CString::new(format!("{name}={value}")).ok())
+        .collect()
+}
+
+/// A valid name starts with a letter or underscore, followed by letters, digits or underscores.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits `NAME=value` into its name and value, if the word is an assignment.
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    is_valid_name(name).then_some((name, value))
+}
@@ -1,13 +1,166 @@
 mod engine;
+pub mod jobs;
 mod resolver;
+pub mod signals;
 
-use super::parser::try_parse_input;
-use anyhow::Result;
+pub use engine::substitute;
+pub use resolver::BUILTINS;
+
+use std::{
+    cell::RefCell,
+    fs,
+    io::Write,
+    path::Path,
+    process,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use super::{
+    environment::set_last_status,
+    parser::{IncompleteInput, try_parse_input},
+};
+use crate::{
+    history,
+    utils::{POISONED_LOCK_MSG_ERR, STDOUT},
+};
+use anyhow::{Error, Result, anyhow};
+use lazy_static::lazy_static;
+
+const MAX_SOURCE_DEPTH: usize = 64;
+
+// How many files are being sourced, one inside the other
+static SOURCE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+type ExitHook = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    // What must be done before the shell goes away, in the order they were added
+    static ref EXIT_HOOKS: Mutex<RefCell<Vec<ExitHook>>> = Mutex::new(RefCell::new(vec![]));
+}
 
 pub fn execute(input: &str) -> Result<()> {
-    if let Some(command) = try_parse_input(input)? {
-        command.exec()?;
+    // the lines of a sourced file weren't typed by the user, they stay out of the history
+    if SOURCE_DEPTH.load(Ordering::Relaxed) > 0 {
+        return evaluate(input);
+    }
+
+    let expanded = history::expand(input).inspect_err(|_| set_last_status(1))?;
+
+    let command = match try_parse_input(&expanded) {
+        Ok(command) => command,
+        Err(e) => {
+            // a syntax error is a failure too, the incomplete input will be retried
+            if !is_incomplete(&e) {
+                set_last_status(2);
+                history::add(&expanded);
+            }
+            return Err(e);
+        }
+    };
+
+    if expanded != input {
+        // Show what the history references turned into
+        let stdout = STDOUT.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut stdout = stdout.borrow_mut();
+        stdout.write_all(expanded.as_bytes())?;
+        stdout.flush()?;
+    }
+    history::add(&expanded);
+
+    if let Some(command) = command {
+        command.run();
+    }
+
+    Ok(())
+}
+
+/// Runs commands that the shell itself was given, like the ones of `PROMPT_COMMAND`, without
+/// the history references nor adding them to the history.
+pub fn evaluate(input: &str) -> Result<()> {
+    let command = try_parse_input(input).inspect_err(|e| {
+        if !is_incomplete(e) {
+            set_last_status(2);
+        }
+    })?;
+
+    if let Some(command) = command {
+        command.run();
     }
 
     Ok(())
 }
+
+/// Executes the commands of a file in the current shell, each one as soon as it's complete, so
+/// an alias defined in a line can be used by the next ones. A syntax error stops the reading.
+pub fn source(path: &Path) -> Result<()> {
+    if SOURCE_DEPTH.load(Ordering::Relaxed) >= MAX_SOURCE_DEPTH {
+        return Err(anyhow!(
+            "{}: maximum source nesting level exceeded ({MAX_SOURCE_DEPTH})",
+            path.display()
+        ));
+    }
+    let content = fs::read_to_string(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+
+    SOURCE_DEPTH.fetch_add(1, Ordering::Relaxed);
+    let mut buffer = String::new();
+    let mut result = Ok(());
+    for line in content.split_inclusive('\n') {
+        buffer.push_str(line);
+        match execute(&buffer) {
+            Err(e) if is_incomplete(&e) => continue,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+            Ok(()) => buffer.clear(),
+        }
+    }
+    SOURCE_DEPTH.fetch_sub(1, Ordering::Relaxed);
+
+    if result.is_ok() && !buffer.is_empty() {
+        set_last_status(2);
+        result = Err(anyhow!(
+            "{}: Unexpected end of file while reading the command",
+            path.display()
+        ));
+    }
+    result
+}
+
+/// Tells if the execution failed only because the input needs more lines, like an open
+/// here-document, so the caller can ask for them and execute again.
+pub fn is_incomplete(error: &Error) -> bool {
+    error.downcast_ref::<IncompleteInput>().is_some()
+}
+
+/// Adds something to be done when the shell exits, by `exit` or by the end of the input.
+pub fn add_exit_hook(hook: impl FnOnce() + Send + 'static) {
+    let hooks = EXIT_HOOKS.lock().expect(POISONED_LOCK_MSG_ERR);
+    hooks.borrow_mut().push(Box::new(hook));
+}
+
+/// The only way out of the shell, it runs the exit hooks first. A forked child, as a pipeline
+/// stage or a command substitution, leaves without them: they save the history and the
+/// hashed commands, or hang up the jobs, of the shell and not of the child.
+pub fn exit_shell(status: i32) -> ! {
+    if jobs::is_forked() {
+        resolver::exit_forked_child(status)
+    }
+
+    // Taken out of the lock, a hook may want to add another one
+    let hooks = {
+        let hooks = EXIT_HOOKS.lock().expect(POISONED_LOCK_MSG_ERR);
+        hooks.take()
+    };
+    for hook in hooks {
+        hook();
+    }
+
+    if let Ok(stdout) = STDOUT.try_lock() {
+        let _ = stdout.borrow_mut().flush();
+    }
+    process::exit(status)
+}
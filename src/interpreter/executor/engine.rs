@@ -1,32 +1,360 @@
-use std::{os::fd::IntoRawFd, path::Path};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    os::{
+        fd::{IntoRawFd, OwnedFd},
+        unix::fs::OpenOptionsExt,
+    },
+    path::Path,
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use nix::{
     errno::Errno,
     fcntl::{OFlag, open},
     libc,
     sys::stat::Mode,
+    unistd::{ForkResult, dup2_stdin, dup2_stdout, fork, pipe},
 };
 
 use crate::{
-    interpreter::parser::{Command, RedirectionTarget, RedirectionType},
-    utils::report_line_err,
+    interpreter::{
+        environment::{
+            get_function, get_positional_params, pop_local_scope, push_local_scope, set_function,
+            set_last_status, set_positional_params, set_var,
+        },
+        expander::{
+            UnsetParameter, expand_assignment, expand_here_document, expand_pattern, expand_word,
+            expand_words, take_substitution_status,
+        },
+        parser::{Command, Redirect, RedirectionTarget, RedirectionType, try_parse_input},
+        pattern,
+    },
+    utils::{POISONED_LOCK_MSG_ERR, STDOUT, report_line_err},
+};
+
+use super::{
+    exit_shell, is_incomplete, jobs,
+    resolver::{CommandExecutor, exit_forked_child, from_command, from_pipeline_stage},
 };
 
-use super::resolver::from_command;
+// How many loops are running, `break` and `continue` only make sense inside one
+static LOOP_DEPTH: AtomicUsize = AtomicUsize::new(0);
+// The loops a `break` or `continue` still has to leave, nothing else runs until they do
+static LOOPS_TO_LEAVE: AtomicUsize = AtomicUsize::new(0);
+// the last loop to leave goes on with its next iteration, it was a `continue`
+static CONTINUING: AtomicBool = AtomicBool::new(false);
+
+/// Leaves the innermost loops, as many as `levels` or every one there is. With `continuing`
+/// the last of them goes on with its next iteration instead.
+pub fn leave_loops(levels: usize, continuing: bool) -> Result<()> {
+    let depth = LOOP_DEPTH.load(Ordering::Relaxed);
+    if depth == 0 {
+        return Err(anyhow!(
+            "only meaningful in a `for', `while', or `until' loop"
+        ));
+    }
+
+    LOOPS_TO_LEAVE.store(levels.min(depth), Ordering::Relaxed);
+    CONTINUING.store(continuing, Ordering::Relaxed);
+    Ok(())
+}
+
+// Deeper than this, it's most likely a recursion without end, and the stack would be over
+// soon after
+const MAX_FUNCTION_DEPTH: usize = 256;
+
+// How many calls to functions are running, one inside the other
+static FUNCTION_DEPTH: AtomicUsize = AtomicUsize::new(0);
+// A `return` is leaving the function, nothing else runs until it does
+static RETURNING: AtomicBool = AtomicBool::new(false);
+
+/// Runs a function with the arguments as its positional parameters, in a scope of its own
+/// for the local variables. Returns the status of the last command it ran, or the one of its
+/// `return`.
+pub fn call_function(name: &str, args: Vec<String>) -> Result<i32> {
+    let Some(body) = get_function(name) else {
+        return Err(anyhow!("{name}: function not found"));
+    };
+    if FUNCTION_DEPTH.load(Ordering::Relaxed) >= MAX_FUNCTION_DEPTH {
+        return Err(anyhow!(
+            "{name}: maximum function nesting level exceeded ({MAX_FUNCTION_DEPTH})"
+        ));
+    }
+
+    FUNCTION_DEPTH.fetch_add(1, Ordering::Relaxed);
+    let params = set_positional_params(args);
+    push_local_scope();
+    // the loops around the call are not for the function to break
+    let loop_depth = LOOP_DEPTH.swap(0, Ordering::Relaxed);
+
+    let status = body.run();
+
+    LOOP_DEPTH.store(loop_depth, Ordering::Relaxed);
+    RETURNING.store(false, Ordering::Relaxed);
+    pop_local_scope();
+    set_positional_params(params);
+    FUNCTION_DEPTH.fetch_sub(1, Ordering::Relaxed);
+
+    Ok(status)
+}
+
+/// Makes the function running return, the commands left in it don't run.
+pub fn return_from_function() -> Result<()> {
+    if FUNCTION_DEPTH.load(Ordering::Relaxed) == 0 {
+        return Err(anyhow!("can only `return' from a function"));
+    }
+
+    RETURNING.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// A `break`, `continue` or `return` is on its way, the commands it skips must not run.
+fn jumping() -> bool {
+    LOOPS_TO_LEAVE.load(Ordering::Relaxed) > 0 || RETURNING.load(Ordering::Relaxed)
+}
+
+/// Tells a loop if it goes on after running its body, a `break`, `continue` or `return`
+/// inside may say otherwise.
+fn next_iteration() -> bool {
+    // Ctrl-C stops the whole loop, not only the command it interrupted
+    if jobs::was_interrupted() || RETURNING.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    match LOOPS_TO_LEAVE.load(Ordering::Relaxed) {
+        0 => true,
+        1 => {
+            LOOPS_TO_LEAVE.store(0, Ordering::Relaxed);
+            CONTINUING.load(Ordering::Relaxed)
+        }
+        levels => {
+            LOOPS_TO_LEAVE.store(levels - 1, Ordering::Relaxed);
+            false
+        }
+    }
+}
 
 impl Command {
-    pub fn exec(self: &Command) -> Result<()> {
-        let mut redirect_helper = RedirectHelper::new();
-        self.configure_redirects(&mut redirect_helper)?;
-        let executable = from_command(self)?;
-        let executable = executable.executable;
+    /// Runs the command the way every command of the shell runs: its errors are reported and
+    /// turned into a failure status, and the status is what `$?` expands to from now on.
+    pub fn run(&self) -> i32 {
+        let status = match self.exec() {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("{}", e);
+                // As POSIX says, only an interactive shell goes on, with the status of bash
+                if e.downcast_ref::<UnsetParameter>().is_some() && !jobs::is_job_control() {
+                    exit_shell(127);
+                }
+                1
+            }
+        };
+
+        set_last_status(status);
+        status
+    }
+
+    pub fn exec(self: &Command) -> Result<i32> {
+        match self {
+            Self::Simple { .. } | Self::Arithmetic(_) => self.exec_simple(),
+            Self::Pipeline {
+                commands,
+                dont_wait,
+            } => exec_pipeline(commands, *dont_wait, self.to_string()),
+            Self::And(left, right) => match left.run() {
+                0 if !jumping() => Ok(right.run()),
+                status => Ok(status),
+            },
+            Self::Or(left, right) => match left.run() {
+                status if status == 0 || jumping() => Ok(status),
+                _ => Ok(right.run()),
+            },
+            Self::List(commands) => {
+                let mut status = 0;
+                for command in commands {
+                    status = command.run();
+                    // a break, continue or return skips the rest of the body
+                    if jumping() {
+                        break;
+                    }
+                }
+
+                Ok(status)
+            }
+            Self::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, body) in branches {
+                    match condition.run() {
+                        status if jumping() => return Ok(status),
+                        0 => return Ok(body.run()),
+                        _ => {}
+                    }
+                }
+
+                // with no branch taken, the status is 0
+                Ok(otherwise.as_ref().map_or(0, |otherwise| otherwise.run()))
+            }
+            Self::While {
+                condition,
+                body,
+                until,
+            } => {
+                LOOP_DEPTH.fetch_add(1, Ordering::Relaxed);
+                jobs::clear_interrupted();
+                let mut status = 0;
+                loop {
+                    let finished = (condition.run() == 0) == *until;
+                    if jumping() || jobs::was_interrupted() {
+                        if next_iteration() {
+                            continue;
+                        }
+                        break;
+                    }
+                    if finished {
+                        break;
+                    }
+
+                    status = body.run();
+                    if !next_iteration() {
+                        break;
+                    }
+                }
+                LOOP_DEPTH.fetch_sub(1, Ordering::Relaxed);
+
+                Ok(status)
+            }
+            Self::For { name, words, body } => {
+                let words = match words {
+                    Some(words) => expand_words(words)?,
+                    None => get_positional_params(),
+                };
 
-        executable()?;
+                LOOP_DEPTH.fetch_add(1, Ordering::Relaxed);
+                jobs::clear_interrupted();
+                let mut status = 0;
+                for word in words {
+                    set_var(name, &word);
+                    status = body.run();
+                    if !next_iteration() {
+                        break;
+                    }
+                }
+                LOOP_DEPTH.fetch_sub(1, Ordering::Relaxed);
+
+                Ok(status)
+            }
+            Self::Case { word, items } => {
+                let word = expand_word(word)?;
+                for (patterns, body) in items {
+                    // the patterns are expanded one by one, only up to the one that matches
+                    for pattern in patterns {
+                        if pattern::matches(&expand_pattern(pattern)?, &word) {
+                            return Ok(body.as_ref().map_or(0, Command::run));
+                        }
+                    }
+                }
+
+                Ok(0)
+            }
+            Self::Group(body) => Ok(body.run()),
+            Self::Function { name, body } => {
+                set_function(name, Arc::clone(body));
+                Ok(0)
+            }
+        }
+    }
+
+    fn exec_simple(&self) -> Result<i32> {
+        let command = self.expand()?;
+        let mut redirect_helper = RedirectHelper::new();
+        // Even if the redirects or the command fail, the shell must get its fds back
+        let result = command
+            .configure_redirects(&mut redirect_helper)
+            .and_then(|_| from_command(&command))
+            .and_then(|executable| (executable.executable)());
 
         redirect_helper.reset_sources()?;
 
-        Ok(())
+        result
+    }
+
+    /// Expands the words of a simple command as it is about to run, so the values are the
+    /// ones of this exact moment.
+    fn expand(&self) -> Result<Command> {
+        // only the substitutions of this command count for its status
+        take_substitution_status();
+
+        match self {
+            Self::Simple {
+                assignments,
+                command_name,
+                args,
+                redirects,
+                dont_wait,
+            } => {
+                let mut words = Vec::with_capacity(args.len() + 1);
+                words.push(command_name.clone());
+                words.extend(args.iter().cloned());
+                let mut words = expand_words(&words)?.into_iter();
+
+                let assignments = assignments
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), expand_assignment(value)?)))
+                    .collect::<Result<Vec<_>>>()?;
+
+                let redirects = redirects
+                    .iter()
+                    .map(|redirect| {
+                        let target = match &redirect.target {
+                            RedirectionTarget::RealFile(file) => {
+                                RedirectionTarget::RealFile(expand_word(file)?)
+                            }
+                            RedirectionTarget::Text(text) => {
+                                RedirectionTarget::Text(expand_word(text)?)
+                            }
+                            RedirectionTarget::HereDocument {
+                                delimiter,
+                                body,
+                                expand: true,
+                            } => RedirectionTarget::HereDocument {
+                                delimiter: delimiter.clone(),
+                                body: expand_here_document(body)?,
+                                expand: false,
+                            },
+                            target => target.clone(),
+                        };
+
+                        Ok(Redirect {
+                            from_fd: redirect.from_fd,
+                            kind: redirect.kind.clone(),
+                            target,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                Ok(Self::Simple {
+                    assignments,
+                    // if everything expanded to nothing, only the assignments are left
+                    command_name: words.next().unwrap_or_default(),
+                    args: words.collect(),
+                    redirects,
+                    dont_wait: *dont_wait,
+                })
+            }
+            Self::Arithmetic(expression) => Ok(Self::Arithmetic(expand_word(expression)?)),
+            _ => Err(anyhow!(
+                "Fatal TSH Error: Only simple commands can be expanded as a whole"
+            )),
+        }
     }
 
     fn configure_redirects(&self, redirect_helper: &mut RedirectHelper) -> Result<()> {
@@ -36,7 +364,11 @@ impl Command {
                     match redirect.kind {
                         RedirectionType::Output => {
                             if let RedirectionTarget::RealFile(file) = &redirect.target {
-                                redirect_helper.redirect_to_file(file, redirect.from_fd, false)?
+                                redirect_helper.redirect_to_file(
+                                    Path::new(file),
+                                    redirect.from_fd,
+                                    false,
+                                )?
                             } else {
                                 // Impossible redirect text output (redirect without @) to file descriptor
                                 report_line_err(Some(
@@ -46,7 +378,11 @@ impl Command {
                         }
                         RedirectionType::AppendOutput => {
                             if let RedirectionTarget::RealFile(file) = &redirect.target {
-                                redirect_helper.redirect_to_file(file, redirect.from_fd, true)?
+                                redirect_helper.redirect_to_file(
+                                    Path::new(file),
+                                    redirect.from_fd,
+                                    true,
+                                )?
                             } else {
                                 // Impossible redirect text output (redirect without @) to file descriptor
                                 report_line_err(Some(
@@ -54,7 +390,39 @@ impl Command {
                                 ));
                             }
                         }
-                        _ => {
+                        RedirectionType::Input => {
+                            if let RedirectionTarget::RealFile(file) = &redirect.target {
+                                redirect_helper
+                                    .redirect_from_file(Path::new(file), redirect.from_fd)?
+                            } else {
+                                // Impossible redirect input (redirect without <<) from other than file
+                                report_line_err(Some(
+                                    "Fatal TSH Error: Input redirection from non file detected",
+                                ));
+                            }
+                        }
+                        RedirectionType::HereDocument { .. } => {
+                            if let RedirectionTarget::HereDocument { body, .. } = &redirect.target {
+                                redirect_helper.redirect_from_text(body, redirect.from_fd)?
+                            } else {
+                                // Impossible here-document (redirect with <<) without body
+                                report_line_err(Some(
+                                    "Fatal TSH Error: Here-document without body detected",
+                                ));
+                            }
+                        }
+                        RedirectionType::HereString => {
+                            if let RedirectionTarget::Text(text) = &redirect.target {
+                                redirect_helper
+                                    .redirect_from_text(&format!("{text}\n"), redirect.from_fd)?
+                            } else {
+                                // Impossible here-string (redirect with <<<) without text
+                                report_line_err(Some(
+                                    "Fatal TSH Error: Here-string without text detected",
+                                ));
+                            }
+                        }
+                        RedirectionType::RedirectToFileDescriptor(_) => {
                             if let RedirectionTarget::FileDescriptor(fd) = &redirect.target {
                                 redirect_helper.redirect_to_fd(redirect.from_fd, *fd)?
                             } else {
@@ -69,6 +437,166 @@ impl Command {
 
                 Ok(())
             }
+            // Only simple commands have redirects, the others are made of simple commands
+            _ => Ok(()),
+        }
+    }
+}
+
+fn exec_pipeline(commands: &[Command], dont_wait: bool, text: String) -> Result<i32> {
+    let mut children = Vec::with_capacity(commands.len());
+    // the first stage leads the process group of the job, the others join it
+    let mut pgid = None;
+    let mut previous_read: Option<OwnedFd> = None;
+
+    // Expand and resolve before forking anything, so a failed expansion doesn't leave stages
+    // behind and the child does not need to touch any lock that other thread could be holding
+    // at the moment of the fork. The compound commands expand as they run, in the child.
+    let stages = commands
+        .iter()
+        .map(|command| match command {
+            Command::Simple { .. } | Command::Arithmetic(_) => {
+                // a stage would run in a child, a missing parameter is no reason for the shell
+                // to exit
+                let command = match command.expand() {
+                    Err(e) if e.is::<UnsetParameter>() => return Err(anyhow!("{e}")),
+                    command => command?,
+                };
+                let executor = from_pipeline_stage(&command)?;
+                Ok(Stage::Resolved(command, executor))
+            }
+            _ => Ok(Stage::Compound(command)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let len = stages.len();
+
+    for (i, stage) in stages.into_iter().enumerate() {
+        let (next_read, write) = if i + 1 < len {
+            let (read, write) = pipe()?;
+            (Some(read), Some(write))
+        } else {
+            (None, None)
+        };
+
+        // SAFETY:
+        // The child only rewires its standard fds, configure the redirects of the stage and runs
+        // the already resolved executor, or the compound command, then it terminates without
+        // returning to the caller. The shell has no other thread that could hold a lock.
+        let fork = unsafe { fork()? };
+        match fork {
+            ForkResult::Child => {
+                jobs::setup_child(pgid, !dont_wait);
+                let status = exec_pipeline_stage(stage, previous_read, write, next_read);
+                exit_forked_child(status)
+            }
+            ForkResult::Parent { child } => {
+                jobs::setup_parent(child, pgid);
+                pgid.get_or_insert(child);
+                children.push(child);
+                // Dropping the write end here is what allows the next stage to receive EOF
+                drop(write);
+                previous_read = next_read;
+            }
+        }
+    }
+
+    // The status of a pipeline is the status of its last command
+    jobs::launch(children, text, dont_wait)
+}
+
+/// Runs the command of a command substitution in a forked child and returns what it writes to
+/// the standard output, without the trailing newlines.
+pub fn substitute(text: &str) -> Result<String> {
+    let command = try_parse_input(text).map_err(|e| match is_incomplete(&e) {
+        // the substitution is already closed, nothing else will come
+        true => anyhow!("Unexpected end of the command substitution: {text}"),
+        false => e,
+    })?;
+    let (read, write) = pipe()?;
+
+    // What the shell didn't write yet must not be written by the child too
+    {
+        let stdout = STDOUT.lock().expect(POISONED_LOCK_MSG_ERR);
+        stdout.borrow_mut().flush()?;
+    }
+    // SAFETY:
+    // The shell has no other thread that could hold a lock, so the child finds every lock it
+    // needs free. It terminates without returning to the caller.
+    let fork = unsafe { fork()? };
+    let child = match fork {
+        ForkResult::Child => {
+            jobs::setup_subshell();
+            drop(read);
+            let status = match dup2_stdout(&write) {
+                Ok(_) => {
+                    drop(write);
+                    command.map_or(0, |command| command.run())
+                }
+                Err(e) => {
+                    eprintln!("{e}");
+                    1
+                }
+            };
+            exit_forked_child(status)
+        }
+        ForkResult::Parent { child } => child,
+    };
+    // Only the child writes, the read end gets EOF once it's over
+    drop(write);
+
+    let mut output = vec![];
+    let read = File::from(read).read_to_end(&mut output);
+    set_last_status(jobs::wait_for_child(child));
+    read?;
+
+    // A string can't hold them for the arguments of a command, as bash they are dropped
+    output.retain(|&b| b != 0);
+    let mut output = String::from_utf8_lossy(&output).into_owned();
+    output.truncate(output.trim_end_matches('\n').len());
+    Ok(output)
+}
+
+/// A stage of a pipeline ready to fork.
+enum Stage<'a> {
+    Resolved(Command, CommandExecutor),
+    Compound(&'a Command),
+}
+
+fn exec_pipeline_stage(
+    stage: Stage,
+    read: Option<OwnedFd>,
+    write: Option<OwnedFd>,
+    unused_read: Option<OwnedFd>,
+) -> i32 {
+    // the read end of the next pipe belongs to the next stage only
+    drop(unused_read);
+
+    let result = (|| -> Result<i32> {
+        if let Some(read) = read {
+            dup2_stdin(&read)?;
+        }
+        if let Some(write) = write {
+            dup2_stdout(&write)?;
+        }
+
+        let (command, executor) = match stage {
+            Stage::Resolved(command, executor) => (command, executor),
+            Stage::Compound(command) => return Ok(command.run()),
+        };
+
+        // The redirects are applied after the pipes, so `cmd 2>@1 | cmd` and `cmd > file | cmd`
+        // behave as expected. There is no need to reset them, the process dies right after.
+        let mut redirect_helper = RedirectHelper::new();
+        command.configure_redirects(&mut redirect_helper)?;
+
+        (executor.executable)()
+    })();
+
+    match result {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
         }
     }
 }
@@ -114,7 +642,7 @@ impl RedirectHelper {
         Ok(())
     }
 
-    fn redirect_to_file(&mut self, file: &Path, fd: i32, append: bool) -> Result<()> {
+    fn save_original(&mut self, fd: i32) -> Result<()> {
         if !self.has_original_for(fd) {
             // Create a duplicated fd for later, we can rollback the file descriptors
             // to its orignal open file descriptors
@@ -128,35 +656,24 @@ impl RedirectHelper {
             self.original_fds.push((result, fd));
         }
 
-        let flags = OFlag::O_CREAT
-            | OFlag::O_RDWR
-            | if append {
-                OFlag::O_APPEND
-            } else {
-                OFlag::O_TRUNC
-            };
-
-        // The actual call from libc returns -1 and errno is setted indicating the error. The
-        // errors that errno can set are described in: https://www.man7.org/linux/man-pages/man2/open.2.html#ERRORS
-        let file_fd: i32 = open(file, flags, Mode::from_bits(0o644).unwrap())?.into_raw_fd();
+        Ok(())
+    }
 
+    fn replace_fd(&mut self, new_fd: i32, fd: i32) -> Result<()> {
         // SAFETY:
         // If the duplication returned some error and setted errno, we catch and return it. If
         // both are the same (like source_fd are the same fd of file), we don't do anything.
-        let result = unsafe { libc::dup2(file_fd, fd) };
+        let result = unsafe { libc::dup2(new_fd, fd) };
         if result == -1 {
             return Err(Errno::last().into());
-        } else if result == fd {
+        } else if new_fd == fd {
             return Ok(());
         }
 
         // SAFETY:
-        // If the duplication returned some error and setted errno, we catch it and proceed.
-        // Here we can safelly close the file_descriptor of file cause now we have the 'fd'
-        // new_fd 'pointing' to the file_fd open file description, so even closign it, the
-        // kernel will mantain the open file description open, cause we still have the 'fd'
-        // 'pointing' to it.
-        let result = unsafe { libc::close(file_fd) };
+        // The 'fd' now 'points' to the same open file description of new_fd, so the kernel
+        // keeps it open even after closing new_fd.
+        let result = unsafe { libc::close(new_fd) };
         if result == -1 {
             return Err(Errno::last().into());
         }
@@ -164,19 +681,59 @@ impl RedirectHelper {
         Ok(())
     }
 
-    fn redirect_to_fd(&mut self, source: i32, dest: i32) -> Result<()> {
-        if !self.has_original_for(source) {
-            // Create a duplicated fd for later, we can rollback the file descriptors
-            // to its orignal open file descriptors
+    fn redirect_from_file(&mut self, file: &Path, fd: i32) -> Result<()> {
+        // Open before touching the fd, so a missing file leaves everything untouched
+        let file_fd: i32 = open(file, OFlag::O_RDONLY, Mode::empty())?.into_raw_fd();
+        self.save_original(fd)?;
+        self.replace_fd(file_fd, fd)
+    }
 
-            // SAFETY:
-            // If the duplication returned some error and setted errno, we catch it and proceed.
-            let result = unsafe { libc::dup(source) };
-            if result == -1 {
-                return Err(Errno::last().into());
-            }
-            self.original_fds.push((result, source));
-        }
+    fn redirect_from_text(&mut self, text: &str, fd: i32) -> Result<()> {
+        // The text goes to an unlinked temporary file instead of a pipe, so we don't need
+        // anyone writing on the other side and big texts can't fill the pipe buffer.
+        static HERE_DOCUMENTS: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "tsh-{}-{}",
+            process::id(),
+            HERE_DOCUMENTS.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let file_fd = {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path)?;
+            fs::remove_file(&path)?;
+            file.write_all(text.as_bytes())?;
+            file.seek(SeekFrom::Start(0))?;
+            file.into_raw_fd()
+        };
+
+        self.save_original(fd)?;
+        self.replace_fd(file_fd, fd)
+    }
+
+    fn redirect_to_file(&mut self, file: &Path, fd: i32, append: bool) -> Result<()> {
+        let flags = OFlag::O_CREAT
+            | OFlag::O_RDWR
+            | if append {
+                OFlag::O_APPEND
+            } else {
+                OFlag::O_TRUNC
+            };
+
+        // The actual call from libc returns -1 and errno is setted indicating the error. The
+        // errors that errno can set are described in: https://www.man7.org/linux/man-pages/man2/open.2.html#ERRORS
+        let file_fd: i32 = open(file, flags, Mode::from_bits(0o644).unwrap())?.into_raw_fd();
+
+        self.save_original(fd)?;
+        self.replace_fd(file_fd, fd)
+    }
+
+    fn redirect_to_fd(&mut self, source: i32, dest: i32) -> Result<()> {
+        self.save_original(source)?;
 
         // SAFETY:
         // If the duplication returned some error and setted errno, we catch and return it. If
@@ -0,0 +1,809 @@
+use std::{
+    cell::RefCell,
+    os::fd::BorrowedFd,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+use nix::{
+    sys::{
+        signal::{SigHandler, Signal, killpg, signal},
+        wait::WaitStatus,
+    },
+    unistd::{Pid, getpgrp, getpid, setpgid, tcgetpgrp, tcsetpgrp},
+};
+
+use crate::{interpreter::environment::set_last_background_pid, utils::POISONED_LOCK_MSG_ERR};
+
+use super::{add_exit_hook, signals};
+
+lazy_static! {
+    static ref JOBS: Mutex<RefCell<JobTable>> = Mutex::new(RefCell::new(JobTable::default()));
+}
+
+// Only the interactive shell controls jobs, the processes forked by it never do
+static JOB_CONTROL: AtomicBool = AtomicBool::new(false);
+// The last job in the foreground was interrupted with Ctrl-C, the loops running it stop too
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// This process is a child forked by the shell, the exit hooks belong to the shell only
+static FORKED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    // by the signal
+    Stopped(i32),
+    // with the exit code
+    Done(i32),
+    // by the signal
+    Killed(i32),
+}
+
+impl JobState {
+    fn is_finished(self) -> bool {
+        matches!(self, Self::Done(_) | Self::Killed(_))
+    }
+
+    /// The conventional status, where being stopped or killed by a signal is 128 plus the
+    /// number of the signal.
+    fn status(self) -> i32 {
+        match self {
+            Self::Running => 0,
+            Self::Done(code) => code,
+            Self::Stopped(signal) | Self::Killed(signal) => 128 + signal,
+        }
+    }
+}
+
+struct Process {
+    pid: Pid,
+    state: JobState,
+}
+
+pub struct Job {
+    id: usize,
+    pgid: Pid,
+    processes: Vec<Process>,
+    command: String,
+    // the last state the user was told about
+    reported: JobState,
+}
+
+impl Job {
+    fn new(pids: Vec<Pid>, command: String) -> Self {
+        Self {
+            id: 0,
+            pgid: pids[0],
+            processes: pids
+                .into_iter()
+                .map(|pid| Process {
+                    pid,
+                    state: JobState::Running,
+                })
+                .collect(),
+            command,
+            reported: JobState::Running,
+        }
+    }
+
+    fn state(&self) -> JobState {
+        if self
+            .processes
+            .iter()
+            .all(|process| process.state.is_finished())
+        {
+            // The status of a job is the status of its last process
+            self.processes
+                .last()
+                .map_or(JobState::Done(0), |process| process.state)
+        } else if self
+            .processes
+            .iter()
+            .any(|process| process.state == JobState::Running)
+        {
+            JobState::Running
+        } else {
+            self.processes
+                .iter()
+                .map(|process| process.state)
+                .find(|state| matches!(state, JobState::Stopped(_)))
+                .unwrap_or(JobState::Running)
+        }
+    }
+
+    /// Applies the change of a process, false if it isn't a process of this job.
+    fn update(&mut self, status: WaitStatus) -> bool {
+        let Some(pid) = status.pid() else {
+            return false;
+        };
+        let Some(process) = self.processes.iter_mut().find(|process| process.pid == pid) else {
+            return false;
+        };
+
+        process.state = match status {
+            WaitStatus::Exited(_, code) => JobState::Done(code),
+            WaitStatus::Signaled(_, signal, _) => JobState::Killed(signal as i32),
+            WaitStatus::Stopped(_, signal) => JobState::Stopped(signal as i32),
+            WaitStatus::Continued(_) => JobState::Running,
+            _ => process.state,
+        };
+
+        true
+    }
+
+    /// Blocks until the job is no longer running, it either ends or stops. The job must be
+    /// out of the table, the changes of the other jobs go to the table meanwhile.
+    fn wait(&mut self) {
+        loop {
+            for status in signals::take_reaped() {
+                if !self.update(status) {
+                    let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+                    jobs.borrow_mut().update(status);
+                }
+            }
+
+            if self.state() != JobState::Running {
+                return;
+            }
+            signals::wait_child();
+        }
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        killpg(self.pgid, Signal::SIGCONT)?;
+        for process in self.processes.iter_mut() {
+            if let JobState::Stopped(_) = process.state {
+                process.state = JobState::Running;
+            }
+        }
+        self.reported = JobState::Running;
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct JobTable {
+    jobs: Vec<Job>,
+    // ids of the jobs from the least to the most recently started, stopped or resumed, the
+    // last is the current job (%+) and the one before it the previous job (%-)
+    recency: Vec<usize>,
+}
+
+impl JobTable {
+    fn insert(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        }
+
+        let id = job.id;
+        let position = self.jobs.partition_point(|other| other.id < id);
+        self.jobs.insert(position, job);
+        self.touch(id);
+
+        id
+    }
+
+    fn touch(&mut self, id: usize) {
+        self.recency.retain(|&other| other != id);
+        self.recency.push(id);
+    }
+
+    fn remove(&mut self, id: usize) -> Option<Job> {
+        self.recency.retain(|&other| other != id);
+        let position = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(position))
+    }
+
+    fn update(&mut self, status: WaitStatus) {
+        // the ones of no job are the disowned, nobody wants to know about them
+        for job in self.jobs.iter_mut() {
+            if job.update(status) {
+                return;
+            }
+        }
+    }
+
+    /// Applies every change of the children since the last look.
+    fn collect(&mut self) {
+        for status in signals::take_reaped() {
+            self.update(status);
+        }
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    fn mark(&self, id: usize) -> char {
+        match self.recency.iter().rev().position(|&other| other == id) {
+            Some(0) => '+',
+            Some(1) => '-',
+            _ => ' ',
+        }
+    }
+
+    /// Resolves a job spec: `%n`, `%%`, `%+`, `%-`, `%prefix` or `%?substring`, no spec is
+    /// the current job.
+    fn find(&self, spec: Option<&str>) -> Result<usize> {
+        let spec = spec.map(|spec| spec.strip_prefix('%').unwrap_or(spec));
+        let spec_name = spec.unwrap_or("%");
+
+        match spec {
+            None | Some("") | Some("%") | Some("+") => self.recency.last().copied(),
+            Some("-") => self.recency.iter().rev().nth(1).copied(),
+            Some(spec) => {
+                if let Ok(id) = spec.parse::<usize>() {
+                    self.jobs.iter().find(|job| job.id == id).map(|job| job.id)
+                } else {
+                    let matching = self
+                        .jobs
+                        .iter()
+                        .filter(|job| match spec.strip_prefix('?') {
+                            Some(substring) => job.command.contains(substring),
+                            None => job.command.starts_with(spec),
+                        })
+                        .collect::<Vec<_>>();
+
+                    if matching.len() > 1 {
+                        return Err(anyhow!("%{spec_name}: ambiguous job spec"));
+                    }
+                    matching.first().map(|job| job.id)
+                }
+            }
+        }
+        .ok_or_else(|| anyhow!("%{spec_name}: no such job"))
+    }
+
+    fn describe(&self, job: &Job, long: bool) -> String {
+        let state = match job.state() {
+            JobState::Running => String::from("Running"),
+            JobState::Stopped(_) => String::from("Stopped"),
+            JobState::Done(0) => String::from("Done"),
+            JobState::Done(status) => format!("Exit {status}"),
+            JobState::Killed(signal) => format!("Killed by signal {signal}"),
+        };
+
+        if long {
+            format!(
+                "[{}]{} {} {state:<24}{}",
+                job.id,
+                self.mark(job.id),
+                job.pgid,
+                job.command
+            )
+        } else {
+            format!(
+                "[{}]{}  {state:<24}{}",
+                job.id,
+                self.mark(job.id),
+                job.command
+            )
+        }
+    }
+}
+
+/// The terminal the shell controls is the one on its standard input.
+fn terminal() -> BorrowedFd<'static> {
+    // SAFETY:
+    // The fd 0 is open for the whole life of the process, it can be redirected for a while,
+    // but never closed.
+    unsafe { BorrowedFd::borrow_raw(0) }
+}
+
+/// Takes control of the terminal, so every command runs as a job in its own process group.
+/// Only makes sense for an interactive shell.
+pub fn init() {
+    // If we were started in background, wait until someone brings us to the foreground
+    while let Ok(foreground) = tcgetpgrp(terminal())
+        && foreground != getpgrp()
+    {
+        let _ = killpg(getpgrp(), Signal::SIGTTIN);
+    }
+
+    // SAFETY:
+    // Ignoring a signal does not install any handler that could run in the middle of
+    // something. The shell must ignore them to give the terminal back and forth.
+    unsafe {
+        let _ = signal(Signal::SIGTTOU, SigHandler::SigIgn);
+        let _ = signal(Signal::SIGTTIN, SigHandler::SigIgn);
+    }
+
+    // It fails when we already lead the session, but then we already are a group leader
+    let _ = setpgid(getpid(), getpid());
+    let _ = tcsetpgrp(terminal(), getpgrp());
+
+    JOB_CONTROL.store(true, Ordering::Relaxed);
+    add_exit_hook(hang_up_stopped_jobs);
+}
+
+/// A stopped job would wait forever for someone to resume it, so they are told the terminal
+/// is gone and woken up to receive it.
+fn hang_up_stopped_jobs() {
+    let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+    let jobs = jobs.borrow();
+
+    for job in jobs.jobs.iter() {
+        if let JobState::Stopped(_) = job.state() {
+            let _ = killpg(job.pgid, Signal::SIGHUP);
+            let _ = killpg(job.pgid, Signal::SIGCONT);
+        }
+    }
+}
+
+pub fn is_job_control() -> bool {
+    JOB_CONTROL.load(Ordering::Relaxed)
+}
+
+/// Tells if this process is a child forked by the shell, and not the shell itself.
+pub fn is_forked() -> bool {
+    FORKED.load(Ordering::Relaxed)
+}
+
+pub fn was_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+pub fn clear_interrupted() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+}
+
+/// Must be called in every child forked to run a command, before anything else. It puts the
+/// child in the process group of its job and gives back the signals the shell ignores.
+pub fn setup_child(pgid: Option<Pid>, foreground: bool) {
+    FORKED.store(true, Ordering::Relaxed);
+    // The children never control jobs, even the ones that keep running shell code
+    if JOB_CONTROL.swap(false, Ordering::Relaxed) {
+        let pgid = pgid.unwrap_or_else(getpid);
+        let _ = setpgid(Pid::from_raw(0), pgid);
+        if foreground {
+            let _ = tcsetpgrp(terminal(), pgid);
+        }
+    }
+
+    reset_signals();
+}
+
+/// Like `setup_child`, for a child that stays in the process group of the shell, as the one
+/// running a command substitution. Ctrl-Z must not stop it, the shell would wait for it
+/// forever.
+pub fn setup_subshell() {
+    FORKED.store(true, Ordering::Relaxed);
+    JOB_CONTROL.store(false, Ordering::Relaxed);
+    reset_signals();
+
+    // SAFETY:
+    // Ignoring a signal does not install any handler.
+    let _ = unsafe { signal(Signal::SIGTSTP, SigHandler::SigIgn) };
+}
+
+fn reset_signals() {
+    // SAFETY:
+    // The default dispositions don't install any handler.
+    unsafe {
+        let _ = signal(Signal::SIGTTOU, SigHandler::SigDfl);
+        let _ = signal(Signal::SIGTTIN, SigHandler::SigDfl);
+    }
+    signals::reset_child();
+}
+
+/// The parent also sets the process group of the child, whoever runs first wins the race
+/// and the process group exists before anyone tries to use it.
+pub fn setup_parent(child: Pid, pgid: Option<Pid>) {
+    if is_job_control() {
+        let _ = setpgid(child, pgid.unwrap_or(child));
+    }
+}
+
+/// Turns the forked processes into a job, the first process leads the process group. In
+/// foreground it waits for the job and returns its status.
+pub fn launch(pids: Vec<Pid>, command: String, background: bool) -> Result<i32> {
+    let job = Job::new(pids, command);
+
+    if background {
+        let last = job.processes.last().map(|process| process.pid);
+        let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+        let id = jobs.borrow_mut().insert(job);
+
+        if let Some(last) = last {
+            set_last_background_pid(last.as_raw());
+            if is_job_control() {
+                eprintln!("[{id}] {last}");
+            }
+        }
+
+        return Ok(0);
+    }
+
+    wait_in_foreground(job)
+}
+
+/// Waits until a child that is not a job, like the one of a command substitution, is over
+/// and returns its status.
+pub fn wait_for_child(pid: Pid) -> i32 {
+    let mut job = Job::new(vec![pid], String::new());
+    loop {
+        job.wait();
+        if job.state().is_finished() {
+            return job.state().status();
+        }
+        // stopped by someone else, it's only over once it's over
+        signals::wait_child();
+    }
+}
+
+fn wait_in_foreground(mut job: Job) -> Result<i32> {
+    if is_job_control() {
+        let _ = tcsetpgrp(terminal(), job.pgid);
+    }
+    job.wait();
+    if is_job_control() {
+        let _ = tcsetpgrp(terminal(), getpgrp());
+    }
+
+    let state = job.state();
+    INTERRUPTED.store(
+        state == JobState::Killed(Signal::SIGINT as i32),
+        Ordering::Relaxed,
+    );
+    match state {
+        // Interrupted on purpose or by a closed pipe, the user already knows
+        JobState::Killed(signal)
+            if signal != Signal::SIGINT as i32 && signal != Signal::SIGPIPE as i32 =>
+        {
+            eprintln!("Killed by signal {signal}");
+            Ok(state.status())
+        }
+        JobState::Stopped(_) => {
+            // It stays in the table until someone resumes it
+            job.reported = state;
+            let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+            let mut jobs = jobs.borrow_mut();
+            let id = jobs.insert(job);
+            if let Some(job) = jobs.jobs.iter().find(|job| job.id == id) {
+                eprintln!("\n{}", jobs.describe(job, false));
+            }
+
+            Ok(state.status())
+        }
+        _ => Ok(state.status()),
+    }
+}
+
+/// Collects what happened to the jobs and tells the user about the changes, the finished
+/// ones leave the table. Called before every prompt.
+pub fn notify() {
+    let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut jobs = jobs.borrow_mut();
+
+    jobs.collect();
+
+    let mut done = vec![];
+    for job in jobs.jobs.iter() {
+        let state = job.state();
+        // only an interactive shell tells about them
+        if state != job.reported && is_job_control() {
+            eprintln!("{}", jobs.describe(job, false));
+        }
+        if state.is_finished() {
+            done.push(job.id);
+        }
+    }
+
+    for job in jobs.jobs.iter_mut() {
+        job.reported = job.state();
+    }
+    for id in done {
+        jobs.remove(id);
+    }
+}
+
+/// Lines describing the jobs, for the `jobs` builtin. The finished ones are shown for the
+/// last time.
+pub fn list(long: bool, only_pgids: bool) -> Vec<String> {
+    let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut jobs = jobs.borrow_mut();
+
+    jobs.collect();
+
+    let lines = jobs
+        .jobs
+        .iter()
+        .map(|job| match only_pgids {
+            true => job.pgid.to_string(),
+            false => jobs.describe(job, long),
+        })
+        .collect();
+
+    let done = jobs
+        .jobs
+        .iter()
+        .filter(|job| job.state().is_finished())
+        .map(|job| job.id)
+        .collect::<Vec<_>>();
+    for id in done {
+        jobs.remove(id);
+    }
+
+    lines
+}
+
+/// Brings a job to the foreground, resuming it if stopped, and waits for it.
+pub fn foreground(spec: Option<&str>) -> Result<i32> {
+    if !is_job_control() {
+        return Err(anyhow!("fg: no job control"));
+    }
+
+    let mut job = {
+        let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut jobs = jobs.borrow_mut();
+        let id = jobs.find(spec)?;
+        jobs.remove(id).ok_or_else(|| anyhow!("fg: no such job"))?
+    };
+
+    eprintln!("{}", job.command);
+    job.resume()?;
+    wait_in_foreground(job)
+}
+
+/// Resumes a stopped job in background.
+pub fn background(spec: Option<&str>) -> Result<()> {
+    if !is_job_control() {
+        return Err(anyhow!("bg: no job control"));
+    }
+
+    let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut jobs = jobs.borrow_mut();
+    let id = jobs.find(spec)?;
+    let Some(job) = jobs.get_mut(id) else {
+        return Err(anyhow!("bg: no such job"));
+    };
+
+    if job.state() == JobState::Running {
+        return Err(anyhow!("bg: job {id} already in background"));
+    }
+    job.resume()?;
+    if let Some(process) = job.processes.last() {
+        set_last_background_pid(process.pid.as_raw());
+    }
+    let command = job.command.clone();
+    jobs.touch(id);
+    eprintln!("[{id}]{} {command}", jobs.mark(id));
+
+    Ok(())
+}
+
+/// Waits for the jobs or process ids given, or every job without any. The status is the one
+/// of the last waited.
+pub fn wait(specs: &[String]) -> Result<i32> {
+    let ids = {
+        let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+        let jobs = jobs.borrow();
+
+        if specs.is_empty() {
+            jobs.jobs.iter().map(|job| Ok(job.id)).collect::<Vec<_>>()
+        } else {
+            specs
+                .iter()
+                .map(|spec| {
+                    if spec.starts_with('%') {
+                        return jobs.find(Some(spec));
+                    }
+
+                    let pid = spec
+                        .parse::<i32>()
+                        .map_err(|_| anyhow!("wait: `{spec}': not a pid or valid job spec"))?;
+                    jobs.jobs
+                        .iter()
+                        .find(|job| job.processes.iter().any(|p| p.pid.as_raw() == pid))
+                        .map(|job| job.id)
+                        .ok_or_else(|| anyhow!("wait: pid {pid} is not a child of this shell"))
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let mut status = 0;
+    for id in ids {
+        let id = match id {
+            Ok(id) => id,
+            Err(e) => {
+                eprintln!("{e}");
+                status = 127;
+                continue;
+            }
+        };
+
+        // Out of the table while waiting, so nobody else touches it
+        let job = {
+            let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+            jobs.borrow_mut().remove(id)
+        };
+        let Some(mut job) = job else {
+            continue;
+        };
+
+        job.wait();
+        let state = job.state();
+        status = state.status();
+        if !state.is_finished() {
+            let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+            jobs.borrow_mut().insert(job);
+        }
+    }
+
+    Ok(status)
+}
+
+/// Removes jobs from the table, so the shell forgets about them.
+pub fn disown(specs: &[String], all: bool) -> Result<()> {
+    let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut jobs = jobs.borrow_mut();
+
+    let ids = if all {
+        jobs.jobs.iter().map(|job| job.id).collect::<Vec<_>>()
+    } else if specs.is_empty() {
+        vec![jobs.find(None)?]
+    } else {
+        specs
+            .iter()
+            .map(|spec| jobs.find(Some(spec)))
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    for id in ids {
+        jobs.remove(id);
+    }
+
+    Ok(())
+}
+
+pub fn has_stopped_jobs() -> bool {
+    let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+    let jobs = jobs.borrow();
+    jobs.jobs
+        .iter()
+        .any(|job| matches!(job.state(), JobState::Stopped(_)))
+}
+
+/// How many jobs are in the table, running, stopped or finished but not told yet.
+pub fn count() -> usize {
+    let jobs = JOBS.lock().expect(POISONED_LOCK_MSG_ERR);
+    let jobs = jobs.borrow();
+    jobs.jobs.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(pids: &[i32], command: &str) -> Job {
+        Job::new(
+            pids.iter().map(|&pid| Pid::from_raw(pid)).collect(),
+            String::from(command),
+        )
+    }
+
+    fn table() -> JobTable {
+        let mut jobs = JobTable::default();
+        jobs.insert(job(&[100], "sleep 10"));
+        jobs.insert(job(&[200, 201], "cat file | grep x"));
+        jobs.insert(job(&[300], "sleep 20"));
+        jobs
+    }
+
+    #[test]
+    fn finds_the_jobs_by_spec() {
+        let jobs = table();
+
+        assert_eq!(jobs.find(None).unwrap(), 3);
+        assert_eq!(jobs.find(Some("%%")).unwrap(), 3);
+        assert_eq!(jobs.find(Some("%+")).unwrap(), 3);
+        assert_eq!(jobs.find(Some("%-")).unwrap(), 2);
+        assert_eq!(jobs.find(Some("%1")).unwrap(), 1);
+        assert_eq!(jobs.find(Some("%cat")).unwrap(), 2);
+        assert_eq!(jobs.find(Some("%?grep")).unwrap(), 2);
+        assert_eq!(jobs.find(Some("%?20")).unwrap(), 3);
+
+        let error = jobs.find(Some("%sleep")).unwrap_err();
+        assert_eq!(error.to_string(), "%sleep: ambiguous job spec");
+        let error = jobs.find(Some("%4")).unwrap_err();
+        assert_eq!(error.to_string(), "%4: no such job");
+        let error = jobs.find(Some("%vi")).unwrap_err();
+        assert_eq!(error.to_string(), "%vi: no such job");
+
+        let empty = JobTable::default();
+        assert!(empty.find(None).is_err());
+        assert!(empty.find(Some("%-")).is_err());
+    }
+
+    #[test]
+    fn numbers_and_marks_the_jobs() {
+        let mut jobs = table();
+        assert_eq!([jobs.mark(1), jobs.mark(2), jobs.mark(3)], [' ', '-', '+']);
+
+        // The current job is the last one touched
+        jobs.touch(1);
+        assert_eq!([jobs.mark(1), jobs.mark(2), jobs.mark(3)], ['+', ' ', '-']);
+
+        // The freed number is taken again only when it's past the highest
+        jobs.remove(2);
+        assert_eq!(jobs.insert(job(&[400], "vi")), 4);
+        jobs.remove(4);
+        jobs.remove(3);
+        assert_eq!(jobs.insert(job(&[500], "vi")), 2);
+        assert_eq!(jobs.find(Some("%-")).unwrap(), 1);
+
+        // A job put back keeps its number and stays in order
+        let mut back = jobs.remove(1).unwrap();
+        back.command = String::from("top");
+        assert_eq!(jobs.insert(back), 1);
+        assert_eq!(
+            jobs.jobs.iter().map(|job| job.id).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(jobs.find(None).unwrap(), 1);
+    }
+
+    #[test]
+    fn follows_the_state_of_the_processes() {
+        let mut jobs = table();
+        let pid = Pid::from_raw;
+
+        jobs.update(WaitStatus::Stopped(pid(201), Signal::SIGTSTP));
+        assert_eq!(jobs.get_mut(2).unwrap().state(), JobState::Running);
+        jobs.update(WaitStatus::Stopped(pid(200), Signal::SIGTSTP));
+        assert_eq!(
+            jobs.get_mut(2).unwrap().state(),
+            JobState::Stopped(Signal::SIGTSTP as i32)
+        );
+        jobs.update(WaitStatus::Continued(pid(200)));
+        jobs.update(WaitStatus::Continued(pid(201)));
+        assert_eq!(jobs.get_mut(2).unwrap().state(), JobState::Running);
+
+        // The status of a pipeline is the one of its last process
+        jobs.update(WaitStatus::Exited(pid(201), 1));
+        assert_eq!(jobs.get_mut(2).unwrap().state(), JobState::Running);
+        jobs.update(WaitStatus::Signaled(pid(200), Signal::SIGPIPE, false));
+        assert_eq!(jobs.get_mut(2).unwrap().state(), JobState::Done(1));
+        assert_eq!(jobs.get_mut(2).unwrap().state().status(), 1);
+
+        jobs.update(WaitStatus::Signaled(pid(300), Signal::SIGKILL, false));
+        let state = jobs.get_mut(3).unwrap().state();
+        assert_eq!(state, JobState::Killed(Signal::SIGKILL as i32));
+        assert_eq!(state.status(), 128 + Signal::SIGKILL as i32);
+
+        // A process of no job, like a disowned one, changes nothing
+        jobs.update(WaitStatus::Exited(pid(999), 0));
+        assert_eq!(jobs.get_mut(1).unwrap().state(), JobState::Running);
+    }
+
+    #[test]
+    fn describes_the_jobs() {
+        let mut jobs = table();
+        jobs.update(WaitStatus::Exited(Pid::from_raw(100), 0));
+        jobs.update(WaitStatus::Exited(Pid::from_raw(300), 2));
+
+        let lines = jobs
+            .jobs
+            .iter()
+            .map(|job| jobs.describe(job, false))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                format!("[1]   {:<24}sleep 10", "Done"),
+                format!("[2]-  {:<24}cat file | grep x", "Running"),
+                format!("[3]+  {:<24}sleep 20", "Exit 2"),
+            ]
+        );
+        assert_eq!(
+            jobs.describe(&jobs.jobs[1], true),
+            format!("[2]- 200 {:<24}cat file | grep x", "Running")
+        );
+    }
+}
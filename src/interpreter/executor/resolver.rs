@@ -1,24 +1,61 @@
 use crate::{
-    interpreter::parser::Command,
-    utils::{EXECUTABLES, POISONED_LOCK_MSG_ERR, STDERR, STDOUT, get_cwd, get_executable_path},
+    completion::{self, CompletionSpec},
+    hash, history,
+    interpreter::{
+        arithmetic,
+        environment::{
+            ENVIRONMENT, OPTION_NAMES, VARIABLES, clear_aliases, exec_environment, export_var,
+            get_alias, get_aliases, get_function, get_last_status, get_var, is_option_set,
+            is_valid_name, make_local, set_alias, set_option, set_positional_params, set_var,
+            split_assignment, unset_alias, unset_function, unset_var,
+        },
+        expander::take_substitution_status,
+        parser::{Command, RESERVED_WORDS},
+    },
+    utils::{POISONED_LOCK_MSG_ERR, STDERR, STDOUT, abbreviate_home, get_cwd},
 };
-use anyhow::{Error, Result};
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
 use nix::{
+    errno::Errno,
     libc,
-    sys::wait::waitpid,
-    unistd::{ForkResult, execve, fork},
+    unistd::{AccessFlags, ForkResult, access, execve, fork},
 };
 use std::{
-    ffi::{CStr, CString},
+    cell::RefCell,
+    env,
+    ffi::CString,
     io::Write,
     os::unix::ffi::OsStrExt,
-    process::exit,
-    thread,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
+use super::{
+    engine::{call_function, leave_loops, return_from_function},
+    exit_shell, jobs, source,
+};
+
+lazy_static! {
+    // The directories saved by pushd, the current directory is not in it
+    static ref DIRECTORY_STACK: Mutex<RefCell<Vec<PathBuf>>> = Mutex::new(RefCell::new(vec![]));
+}
+/// The names the shell runs by itself, without looking for them in PATH.
+pub const BUILTINS: &[&str] = &[
+    ".", "alias", "bg", "break", "builtin", "cd", "command", "complete", "continue", "dirs",
+    "disown", "echo", "env", "exit", "export", "fg", "hash", "history", "jobs", "let", "local",
+    "popd", "pushd", "pwd", "return", "set", "source", "type", "unalias", "unset", "wait", "which",
+];
+
+// exit only warns once about the stopped jobs, the second time it really exits
+static WARNED_STOPPED_JOBS: AtomicBool = AtomicBool::new(false);
+
 pub struct CommandExecutor {
     pub target_type: TargetExecutor,
-    pub executable: Box<dyn FnOnce() -> Result<()> + 'static + Send>,
+    pub executable: Box<dyn FnOnce() -> Result<i32> + 'static + Send>,
 }
 
 pub enum TargetExecutor {
@@ -27,57 +64,87 @@ pub enum TargetExecutor {
 }
 
 pub fn from_command(command: &Command) -> Result<CommandExecutor> {
+    resolve(command, false)
+}
+
+/// Resolves a command that already runs in its own forked process, like a pipeline stage. Here
+/// external commands replace the process image instead of forking again, and jobs make no sense.
+pub fn from_pipeline_stage(command: &Command) -> Result<CommandExecutor> {
+    resolve(command, true)
+}
+
+fn resolve(command: &Command, forked: bool) -> Result<CommandExecutor> {
     #[allow(unused_assignments)] // this is being used, but is saying that isn't
     let mut job = false;
-    
+    // how the job is shown by `jobs`
+    let text = command.to_string();
+
     let mut executor = match command {
         Command::Simple {
+            assignments,
             command_name,
             args,
             dont_wait,
             ..
         } => {
-            job = *dont_wait;
-            let cmd_name = &str::to_lowercase(command_name)[..];
-            match cmd_name {
-                "echo" => Ok::<CommandExecutor, Error>(CommandExecutor {
+            job = *dont_wait && !forked;
+            match command_name.as_str() {
+                // the functions go before the builtins and the executables with the same name
+                _ if get_function(command_name).is_some() => Ok(CommandExecutor {
                     target_type: TargetExecutor::Builtin,
-                    executable: build_echo_exec(args),
+                    executable: build_function_exec(command_name, args),
                 }),
-                "exit" => Ok(CommandExecutor {
+                // only assignments, without any command to run
+                "" => Ok(CommandExecutor {
                     target_type: TargetExecutor::Builtin,
-                    executable: build_exit_exec(args),
-                }),
-                "pwd" => Ok(CommandExecutor {
-                    target_type: TargetExecutor::Builtin,
-                    executable: build_pwd_exec(),
-                }),
-                _ => Ok(CommandExecutor {
-                    target_type: TargetExecutor::Ext,
-                    executable: build_ext_exec(command_name, args, *dont_wait),
+                    executable: build_assignments_exec(assignments),
                 }),
+                _ => resolve_simple(command_name, args, assignments, &text, job, forked),
             }
         }
+        Command::Arithmetic(expression) => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_arithmetic_exec(expression),
+        }),
+        _ => Err(anyhow!(
+            "Fatal TSH Error: Only simple commands can be resolved to a single executor"
+        )),
     }?;
 
     match executor.target_type {
         TargetExecutor::Builtin => {
             fn build_builtin_exec_jobbed_from_original<E>(
                 e: E,
-            ) -> Box<dyn FnOnce() -> Result<()> + 'static + Send>
+                text: String,
+            ) -> Box<dyn FnOnce() -> Result<i32> + 'static + Send>
             where
-                E: FnOnce() -> Result<()> + 'static + Send,
+                E: FnOnce() -> Result<i32> + 'static + Send,
             {
                 Box::new(move || {
-                    thread::spawn(move || {
-                        let _ = e();
-                    });
-                    Ok(())
+                    // SAFETY:
+                    // The child runs the builtin and terminates without returning to the
+                    // caller, the same as a pipeline stage.
+                    let fork = unsafe { fork()? };
+                    match fork {
+                        ForkResult::Child => {
+                            jobs::setup_child(None, false);
+                            let status = e().unwrap_or_else(|e| {
+                                eprintln!("{}", e);
+                                1
+                            });
+                            exit_forked_child(status)
+                        }
+                        ForkResult::Parent { child } => {
+                            jobs::setup_parent(child, None);
+                            jobs::launch(vec![child], text, true)
+                        }
+                    }
                 })
             }
 
             if job {
-                executor.executable = build_builtin_exec_jobbed_from_original(executor.executable);
+                executor.executable =
+                    build_builtin_exec_jobbed_from_original(executor.executable, text);
             }
 
             Ok(executor)
@@ -86,8 +153,170 @@ pub fn from_command(command: &Command) -> Result<CommandExecutor> {
     }
 }
 
+/// Resolves a simple command by its name alone, as a builtin or as an executable, leaving the
+/// functions out. The names are case sensitive, `ECHO` is looked for in PATH. This is what
+/// `command` and `builtin` run their commands with.
+fn resolve_simple(
+    command_name: &str,
+    args: &[String],
+    assignments: &[(String, String)],
+    text: &str,
+    job: bool,
+    forked: bool,
+) -> Result<CommandExecutor> {
+    match command_name {
+        "echo" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_echo_exec(args),
+        }),
+        "exit" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_exit_exec(args),
+        }),
+        "pwd" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_pwd_exec(),
+        }),
+        "cd" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_cd_exec(args),
+        }),
+        "pushd" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_pushd_exec(args),
+        }),
+        "popd" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_popd_exec(args),
+        }),
+        "dirs" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_dirs_exec(args),
+        }),
+        "export" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_export_exec(args),
+        }),
+        "unset" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_unset_exec(args),
+        }),
+        "set" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_set_exec(args),
+        }),
+        "history" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_history_exec(args),
+        }),
+        "type" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_type_exec(args),
+        }),
+        "which" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_which_exec(args),
+        }),
+        "command" => match args.first().map(String::as_str) {
+            // only telling what the names are
+            Some("-v" | "-V") | None => Ok(CommandExecutor {
+                target_type: TargetExecutor::Builtin,
+                executable: build_command_exec(args),
+            }),
+            Some("--") if args.len() == 1 => Ok(CommandExecutor {
+                target_type: TargetExecutor::Builtin,
+                executable: build_command_exec(&[]),
+            }),
+            Some("--") => resolve_simple(&args[1], &args[2..], assignments, text, job, forked),
+            Some(option) if option.starts_with('-') => Ok(CommandExecutor {
+                target_type: TargetExecutor::Builtin,
+                executable: build_command_exec(args),
+            }),
+            Some(name) => resolve_simple(name, &args[1..], assignments, text, job, forked),
+        },
+        "builtin" => match args.split_first() {
+            Some((name, args)) if BUILTINS.contains(&name.as_str()) => {
+                resolve_simple(name, args, assignments, text, job, forked)
+            }
+            Some((name, _)) => Ok(CommandExecutor {
+                target_type: TargetExecutor::Builtin,
+                executable: build_not_builtin_exec(name),
+            }),
+            None => Ok(CommandExecutor {
+                target_type: TargetExecutor::Builtin,
+                executable: Box::new(|| Ok(0)),
+            }),
+        },
+        "hash" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_hash_exec(args),
+        }),
+        "jobs" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_jobs_exec(args),
+        }),
+        "fg" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_fg_exec(args),
+        }),
+        "bg" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_bg_exec(args),
+        }),
+        "wait" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_wait_exec(args),
+        }),
+        "disown" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_disown_exec(args),
+        }),
+        "complete" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_complete_exec(args),
+        }),
+        "let" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_let_exec(args),
+        }),
+        "break" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_break_exec(args),
+        }),
+        "continue" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_continue_exec(args),
+        }),
+        "local" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_local_exec(args),
+        }),
+        "return" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_return_exec(args),
+        }),
+        "alias" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_alias_exec(args),
+        }),
+        "unalias" => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_unalias_exec(args),
+        }),
+        "source" | "." => Ok(CommandExecutor {
+            target_type: TargetExecutor::Builtin,
+            executable: build_source_exec(command_name, args),
+        }),
+        "env" => Ok(build_env_executor(args, assignments, text, job, forked)),
+        _ => Ok(CommandExecutor {
+            target_type: TargetExecutor::Ext,
+            executable: build_ext_exec(command_name, args, assignments, text, job, forked),
+        }),
+    }
+}
+
 #[inline(always)]
-fn build_echo_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<()> + Send> {
+fn build_echo_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
     // TODO: Refactor to not clone args.
     let args = args.to_owned();
     Box::new(move || {
@@ -104,36 +333,396 @@ fn build_echo_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<()> + Send> {
         stdout.write_all(b"\n")?;
         stdout.flush()?;
 
-        Ok(())
+        Ok(0)
     })
 }
 
 #[inline(always)]
-fn build_exit_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<()> + Send> {
+fn build_exit_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
     // TODO: Refactor to not clone args.
     let args = args.to_owned();
     Box::new(move || {
+        // Only the interactive shell warns, a subshell or a pipeline stage exits right away
+        if jobs::is_job_control()
+            && jobs::has_stopped_jobs()
+            && !WARNED_STOPPED_JOBS.swap(true, Ordering::Relaxed)
+        {
+            return Err(anyhow!("There are stopped jobs."));
+        }
+
         if let Some(exit_code) = args.first() {
             if let Ok(exit_code) = exit_code.parse::<i32>() {
-                exit(exit_code)
+                exit_shell(exit_code)
             } else {
-                exit(0)
+                exit_shell(0)
             }
         } else {
-            exit(0)
+            // without a code, exits with the status of the last command
+            exit_shell(get_last_status())
         }
     })
 }
 
 #[inline(always)]
-fn build_pwd_exec() -> Box<dyn FnOnce() -> Result<()> + Send> {
-    Box::new(|| -> Result<()> {
+fn build_pwd_exec() -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    Box::new(|| -> Result<i32> {
         let stdout = STDOUT.lock().expect(POISONED_LOCK_MSG_ERR);
         let mut stdout = stdout.borrow_mut();
         stdout.write_all(get_cwd()?.as_os_str().as_bytes())?;
         stdout.write_all(b"\n")?;
 
-        Ok(())
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_cd_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let (target, print) = match args.first().map(String::as_str) {
+            None => (
+                get_var("HOME").ok_or_else(|| anyhow!("cd: HOME not set"))?,
+                false,
+            ),
+            Some("-") => (
+                get_var("OLDPWD").ok_or_else(|| anyhow!("cd: OLDPWD not set"))?,
+                true,
+            ),
+            Some(dir) => match search_cdpath(dir) {
+                Some(found) => (found, true),
+                None => (dir.to_owned(), false),
+            },
+        };
+
+        change_directory(&target).map_err(|e| anyhow!("cd: {target}: {e}"))?;
+
+        if print {
+            print_line(&get_cwd()?.to_string_lossy())?;
+        }
+
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_pushd_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let current = get_cwd()?;
+        let stack = DIRECTORY_STACK.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut stack = stack.borrow_mut();
+
+        match args.first() {
+            // without a directory, exchanges the current one with the top of the stack
+            None => {
+                let Some(top) = stack.first().cloned() else {
+                    return Err(anyhow!("pushd: no other directory"));
+                };
+                change_directory(&top.to_string_lossy())
+                    .map_err(|e| anyhow!("pushd: {}: {e}", top.display()))?;
+                stack[0] = current;
+            }
+            Some(dir) => {
+                change_directory(dir).map_err(|e| anyhow!("pushd: {dir}: {e}"))?;
+                stack.insert(0, current);
+            }
+        }
+
+        print_line(&format_directory_stack(&stack)?)?;
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_popd_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        if let Some(arg) = args.first() {
+            return Err(anyhow!("popd: {arg}: invalid argument"));
+        }
+
+        let stack = DIRECTORY_STACK.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut stack = stack.borrow_mut();
+        let Some(top) = stack.first().cloned() else {
+            return Err(anyhow!("popd: directory stack empty"));
+        };
+
+        change_directory(&top.to_string_lossy())
+            .map_err(|e| anyhow!("popd: {}: {e}", top.display()))?;
+        stack.remove(0);
+
+        print_line(&format_directory_stack(&stack)?)?;
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_dirs_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let stack = DIRECTORY_STACK.lock().expect(POISONED_LOCK_MSG_ERR);
+        let mut stack = stack.borrow_mut();
+
+        match args.first().map(String::as_str) {
+            None => print_line(&format_directory_stack(&stack)?)?,
+            Some("-c") => stack.clear(),
+            // -p one per line, -v one per line with its position
+            Some(option @ ("-p" | "-v")) => {
+                let current = get_cwd()?;
+                let directories = [&current].into_iter().chain(stack.iter());
+                for (i, directory) in directories.enumerate() {
+                    let directory = abbreviate_home(directory);
+                    if option == "-v" {
+                        print_line(&format!("{i:2}  {directory}"))?;
+                    } else {
+                        print_line(&directory)?;
+                    }
+                }
+            }
+            Some(arg) => return Err(anyhow!("dirs: {arg}: invalid option")),
+        }
+
+        Ok(0)
+    })
+}
+
+/// Changes the working directory of the shell, keeping `PWD` and `OLDPWD` up to date.
+fn change_directory(target: &str) -> Result<()> {
+    let old = get_var("PWD").map(PathBuf::from).map_or_else(get_cwd, Ok)?;
+
+    env::set_current_dir(target).map_err(|e| match e.raw_os_error() {
+        Some(code) => anyhow!("{}", Errno::from_raw(code).desc()),
+        None => e.into(),
+    })?;
+
+    set_var("OLDPWD", &old.to_string_lossy());
+    set_var("PWD", &get_cwd()?.to_string_lossy());
+
+    Ok(())
+}
+
+/// Looks for a relative directory in the directories of `CDPATH`.
+fn search_cdpath(dir: &str) -> Option<String> {
+    if dir.starts_with('/') || dir.starts_with('.') || dir.starts_with('~') {
+        return None;
+    }
+
+    let cdpath = get_var("CDPATH")?;
+    env::split_paths(&cdpath)
+        // an empty entry is the current directory, which is the default anyway
+        .filter(|base| !base.as_os_str().is_empty())
+        .map(|base| base.join(dir))
+        .find(|candidate| candidate.is_dir())
+        .map(|found| found.to_string_lossy().into_owned())
+}
+
+fn format_directory_stack(stack: &[PathBuf]) -> Result<String> {
+    let current = get_cwd()?;
+    Ok([&current]
+        .into_iter()
+        .chain(stack.iter())
+        .map(|directory| abbreviate_home(directory))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+fn print_line(line: &str) -> Result<()> {
+    let stdout = STDOUT.lock().expect(POISONED_LOCK_MSG_ERR);
+    let mut stdout = stdout.borrow_mut();
+    stdout.write_all(line.as_bytes())?;
+    stdout.write_all(b"\n")?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+#[inline(always)]
+fn build_export_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        if args.is_empty() {
+            let environment = ENVIRONMENT.lock().expect(POISONED_LOCK_MSG_ERR);
+            let environment = environment.borrow();
+            let stdout = STDOUT.lock().expect(POISONED_LOCK_MSG_ERR);
+            let mut stdout = stdout.borrow_mut();
+
+            for (name, value) in environment.iter() {
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                stdout.write_all(format!("export {name}=\"{value}\"\n").as_bytes())?;
+            }
+            stdout.flush()?;
+
+            return Ok(0);
+        }
+
+        for arg in args.iter() {
+            let (name, value) = arg.split_once('=').unwrap_or((arg, ""));
+            if !is_valid_name(name) {
+                return Err(anyhow!("export: `{arg}': not a valid identifier"));
+            }
+
+            // `export NAME` keeps the current value, if there is one
+            if arg.contains('=') {
+                set_var(name, value);
+            }
+            export_var(name);
+        }
+
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_unset_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        // -f for functions, -v for variables, the default
+        let functions = args.first().is_some_and(|arg| arg == "-f");
+        let names = match args.first().map(String::as_str) {
+            Some("-f" | "-v") => &args[1..],
+            _ => &args[..],
+        };
+
+        for name in names {
+            if functions {
+                unset_function(name);
+                continue;
+            }
+            if !is_valid_name(name) {
+                return Err(anyhow!("unset: `{name}': not a valid identifier"));
+            }
+            unset_var(name);
+        }
+
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_set_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        if args.is_empty() {
+            // every variable, exported or not
+            let mut variables = {
+                let environment = ENVIRONMENT.lock().expect(POISONED_LOCK_MSG_ERR);
+                environment.borrow().clone()
+            };
+            {
+                let shell_variables = VARIABLES.lock().expect(POISONED_LOCK_MSG_ERR);
+                variables.extend(shell_variables.borrow().clone());
+            }
+
+            for (name, value) in variables {
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                print_line(&format!("{name}=\"{value}\""))?;
+            }
+            return Ok(0);
+        }
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" | "+o" => match args.next() {
+                    Some(name) => set_option(name, arg == "-o").map_err(|e| anyhow!("set: {e}"))?,
+                    None => {
+                        for name in OPTION_NAMES {
+                            let state = if is_option_set(name) { "on" } else { "off" };
+                            print_line(&format!("{name:<15}\t{state}"))?;
+                        }
+                    }
+                },
+                // the rest are the new positional parameters
+                "--" => {
+                    set_positional_params(args.cloned().collect());
+                    break;
+                }
+                _ if arg.starts_with(['-', '+']) => {
+                    return Err(anyhow!("set: {arg}: invalid option"));
+                }
+                _ => {
+                    set_positional_params([arg.clone()].into_iter().chain(args.cloned()).collect());
+                    break;
+                }
+            }
+        }
+
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_env_executor(
+    args: &[String],
+    assignments: &[(String, String)],
+    text: &str,
+    job: bool,
+    forked: bool,
+) -> CommandExecutor {
+    // env [NAME=value]... [command [args]...], the options are left to the env of the system
+    if args.first().is_some_and(|arg| arg.starts_with('-')) {
+        return CommandExecutor {
+            target_type: TargetExecutor::Ext,
+            executable: build_ext_exec("env", args, assignments, text, job, forked),
+        };
+    }
+
+    let mut assignments = assignments.to_owned();
+    let mut args = args.iter();
+    let command_name = loop {
+        match args.next() {
+            Some(arg) => match split_assignment(arg) {
+                Some((name, value)) => assignments.push((name.to_owned(), value.to_owned())),
+                None => break Some(arg),
+            },
+            None => break None,
+        }
+    };
+
+    if let Some(command_name) = command_name {
+        let args = args.cloned().collect::<Vec<_>>();
+        return CommandExecutor {
+            target_type: TargetExecutor::Ext,
+            executable: build_ext_exec(command_name, &args, &assignments, text, job, forked),
+        };
+    }
+
+    CommandExecutor {
+        target_type: TargetExecutor::Builtin,
+        executable: Box::new(move || {
+            let stdout = STDOUT.lock().expect(POISONED_LOCK_MSG_ERR);
+            let mut stdout = stdout.borrow_mut();
+
+            for variable in exec_environment(&assignments) {
+                stdout.write_all(variable.as_bytes())?;
+                stdout.write_all(b"\n")?;
+            }
+            stdout.flush()?;
+
+            Ok(0)
+        }),
+    }
+}
+
+#[inline(always)]
+fn build_assignments_exec(
+    assignments: &[(String, String)],
+) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    let assignments = assignments.to_owned();
+    Box::new(move || {
+        for (name, value) in assignments.iter() {
+            set_var(name, value);
+        }
+
+        // `out=$(cmd)` tells how cmd went
+        Ok(take_substitution_status().unwrap_or(0))
     })
 }
 
@@ -141,33 +730,52 @@ fn build_pwd_exec() -> Box<dyn FnOnce() -> Result<()> + Send> {
 fn build_ext_exec(
     command_name: &str,
     args: &[String],
+    assignments: &[(String, String)],
+    text: &str,
     job: bool,
-) -> Box<dyn FnOnce() -> Result<()> + Send> {
+    in_place: bool,
+) -> Box<dyn FnOnce() -> Result<i32> + Send> {
     // TODO: Refactor to not clone args.
     let command_name = command_name.to_owned();
+    let text = text.to_owned();
     let args = args.to_owned();
+    let env = exec_environment(assignments);
+
+    // With a slash it's a path already, anything else is looked for in PATH. The lookup
+    // happens here, and not inside the executable, so pipeline stages resolve their
+    // executables before forking and the hits are counted by the shell.
+    let executable_path = match command_name.contains('/') {
+        true => Some(PathBuf::from(&command_name)),
+        false => hash::find(&command_name),
+    };
+
     Box::new(move || {
-        let executables = EXECUTABLES.lock().expect(POISONED_LOCK_MSG_ERR);
-        let executables = executables.borrow();
-        let executable_path = get_executable_path(&command_name[..], &executables);
         let stderr = STDERR.lock().expect(POISONED_LOCK_MSG_ERR);
         let mut stderr = stderr.borrow_mut();
 
         if let Some(path) = executable_path {
-            let c_path = CString::new(
-                path.as_os_str()
-                    .to_str()
-                    .expect("Impossible to fail cause the Path is valid utf8 str")
-                    .as_bytes(),
-            )
-            .expect("Impossible to have \\0 in Rust string");
+            if let Some((status, reason)) = cannot_execute(&path) {
+                stderr.write_all(format!("{command_name}: {reason}\n").as_bytes())?;
+                return Ok(status);
+            }
+
+            // a NUL would cut the string short, execve can't take it
+            let nul = |_| anyhow!("{command_name}: an argument has a NUL byte");
+            let c_path = CString::new(path.as_os_str().as_bytes()).map_err(nul)?;
             let mut args = args
                 .iter()
-                .map(|s| CString::new(s.as_bytes()).expect("Impossible have \\0 in Rust string"))
-                .collect::<Vec<_>>();
+                .map(|s| CString::new(s.as_bytes()).map_err(nul))
+                .collect::<Result<Vec<_>>>()?;
             args.insert(0, c_path.clone());
             let args = args.iter().map(|s| s.as_c_str()).collect::<Vec<_>>();
-            let env: Vec<&CStr> = vec![];
+            let env = env.iter().map(|s| s.as_c_str()).collect::<Vec<_>>();
+
+            if in_place {
+                // We are already in a child process, just replace its image. If it
+                // returns, execve failed.
+                let Err(e) = execve(c_path.as_c_str(), &args, &env);
+                return Err(e.into());
+            }
 
             // SAFETY:
             // We are immediatly invoking execve after fork, so no 'abandoned locks'
@@ -176,7 +784,8 @@ fn build_ext_exec(
             let fork = unsafe { fork()? };
             match fork {
                 ForkResult::Child => {
-                    execve(c_path.as_c_str(), &args, &env)?;
+                    jobs::setup_child(None, !job);
+                    let _ = execve(c_path.as_c_str(), &args, &env);
 
                     // SAFETY:
                     // If we touch here, means that execve call not work and didnt replaced
@@ -190,15 +799,738 @@ fn build_ext_exec(
                     unsafe { libc::exit(1) };
                 }
                 ForkResult::Parent { child, .. } => {
-                    if !job {
-                        waitpid(child, None)?;
-                    }
+                    // Release stderr, the job may stay in foreground for a long while
+                    drop(stderr);
+                    jobs::setup_parent(child, None);
+                    jobs::launch(vec![child], text, job)
                 }
             }
         } else {
             stderr.write_all(format!("Command not found: {}\n", command_name).as_bytes())?;
+            Ok(127)
+        }
+    })
+}
+
+/// Why the file can't be executed, with the status it gives: 127 when it isn't there and 126
+/// when it is but can't run.
+fn cannot_execute(path: &Path) -> Option<(i32, &'static str)> {
+    if !path.exists() {
+        Some((127, "No such file or directory"))
+    } else if path.is_dir() {
+        Some((126, "Is a directory"))
+    } else if access(path, AccessFlags::X_OK).is_err() {
+        Some((126, "Permission denied"))
+    } else {
+        None
+    }
+}
+
+#[inline(always)]
+fn build_history_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        match args.first().map(String::as_str) {
+            Some("-c") => history::clear(),
+            Some("-d") => {
+                let number = args
+                    .get(1)
+                    .and_then(|number| number.parse().ok())
+                    .ok_or_else(|| anyhow!("history: -d: a position is required"))?;
+                history::delete(number).map_err(|e| anyhow!("history: {e}"))?;
+            }
+            // append the new lines, or write all of them
+            Some("-a") => history::save().map_err(|e| anyhow!("history: {e}"))?,
+            Some("-w") => history::write().map_err(|e| anyhow!("history: {e}"))?,
+            count => {
+                let count = match count {
+                    Some(count) => Some(
+                        count
+                            .parse()
+                            .map_err(|_| anyhow!("history: {count}: numeric argument required"))?,
+                    ),
+                    None => None,
+                };
+
+                let time_format = get_var("HISTTIMEFORMAT");
+                for (number, entry) in history::list(count) {
+                    let time = match &time_format {
+                        Some(format) => history::format_timestamp(entry.timestamp, format),
+                        None => String::new(),
+                    };
+                    print_line(&format!("{number:5}  {time}{}", entry.line))?;
+                }
+            }
+        }
+
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_hash_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let (option, names) = match args.split_first() {
+            Some((option, names)) if option.starts_with('-') => (Some(option.as_str()), names),
+            _ => (None, &args[..]),
+        };
+
+        let mut status = 0;
+        match option {
+            None if names.is_empty() => {
+                let hashed = hash::hashed();
+                if hashed.is_empty() {
+                    print_line("hash: hash table empty")?;
+                    return Ok(0);
+                }
+
+                print_line("hits\tcommand")?;
+                for (_, path, hits) in hashed {
+                    print_line(&format!("{hits:4}\t{}", path.display()))?;
+                }
+            }
+            // forget everything, then look for the names again
+            None | Some("-r") => {
+                if option.is_some() {
+                    hash::forget_all();
+                }
+                for name in names.iter() {
+                    // the builtins are never looked for
+                    if !BUILTINS.contains(&name.as_str()) && hash::remember(name).is_none() {
+                        eprintln!("hash: {name}: not found");
+                        status = 1;
+                    }
+                }
+            }
+            Some("-p") => {
+                let [path, names @ ..] = names else {
+                    return Err(anyhow!("hash: -p: option requires an argument"));
+                };
+                if names.is_empty() {
+                    return Err(anyhow!(
+                        "hash: usage: hash [-r] [-p pathname] [-dt] [name ...]"
+                    ));
+                }
+                for name in names.iter() {
+                    hash::set(name, Path::new(path));
+                }
+            }
+            Some("-d") => {
+                for name in names.iter() {
+                    if !hash::forget(name) {
+                        eprintln!("hash: {name}: not found");
+                        status = 1;
+                    }
+                }
+            }
+            Some("-t") => {
+                let hashed = hash::hashed();
+                for name in names.iter() {
+                    match hashed.iter().find(|(hashed, ..)| hashed == name) {
+                        // with many names, each one goes before its path
+                        Some((_, path, _)) if names.len() > 1 => {
+                            print_line(&format!("{name}\t{}", path.display()))?
+                        }
+                        Some((_, path, _)) => print_line(&path.to_string_lossy())?,
+                        None => {
+                            eprintln!("hash: {name}: not found");
+                            status = 1;
+                        }
+                    }
+                }
+            }
+            Some(option) => return Err(anyhow!("hash: {option}: invalid option")),
+        }
+
+        Ok(status)
+    })
+}
+
+#[inline(always)]
+fn build_jobs_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let mut long = false;
+        let mut only_pgids = false;
+        for arg in args.iter() {
+            match arg.as_str() {
+                "-l" => long = true,
+                "-p" => only_pgids = true,
+                _ => return Err(anyhow!("jobs: {arg}: invalid option")),
+            }
+        }
+
+        for line in jobs::list(long, only_pgids) {
+            print_line(&line)?;
+        }
+
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_fg_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || jobs::foreground(args.first().map(String::as_str)))
+}
+
+#[inline(always)]
+fn build_bg_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        jobs::background(args.first().map(String::as_str))?;
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_wait_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || jobs::wait(&args))
+}
+
+#[inline(always)]
+fn build_disown_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let all = args.first().is_some_and(|arg| arg == "-a");
+        let specs = if all { &args[1..] } else { &args[..] };
+        jobs::disown(specs, all)?;
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_let_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        if args.is_empty() {
+            return Err(anyhow!("let: expression expected"));
+        }
+
+        let mut value = 0;
+        for expression in args.iter() {
+            value = arithmetic::evaluate(expression).map_err(|e| anyhow!("let: {e}"))?;
+        }
+
+        // as in C, zero is false
+        Ok(if value != 0 { 0 } else { 1 })
+    })
+}
+
+#[inline(always)]
+fn build_arithmetic_exec(expression: &str) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    let expression = expression.to_owned();
+    Box::new(move || {
+        let value = arithmetic::evaluate(&expression).map_err(|e| anyhow!("((: {e}"))?;
+        Ok(if value != 0 { 0 } else { 1 })
+    })
+}
+
+#[inline(always)]
+fn build_function_exec(name: &str, args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    let name = name.to_owned();
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || call_function(&name, args))
+}
+
+#[inline(always)]
+fn build_local_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        for arg in args.iter() {
+            let (name, value) = match split_assignment(arg) {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_valid_name(name) {
+                return Err(anyhow!("local: `{arg}': not a valid identifier"));
+            }
+
+            make_local(name).map_err(|e| anyhow!("local: {e}"))?;
+            if let Some(value) = value {
+                set_var(name, value);
+            }
+        }
+
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_return_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        // without a status, the one of the last command
+        let status = match &args[..] {
+            [] => get_last_status(),
+            [status] => status
+                .parse::<i32>()
+                .map_err(|_| anyhow!("return: {status}: numeric argument required"))?,
+            _ => return Err(anyhow!("return: too many arguments")),
+        };
+
+        return_from_function().map_err(|e| anyhow!("return: {e}"))?;
+        Ok(status & 0xff)
+    })
+}
+
+#[inline(always)]
+fn build_alias_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        if args.is_empty() {
+            for (name, value) in get_aliases() {
+                print_line(&format_alias(&name, &value))?;
+            }
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for arg in args.iter() {
+            match arg.split_once('=') {
+                Some((name, _)) if !is_alias_name(name) => {
+                    eprintln!("alias: `{name}': invalid alias name");
+                    status = 1;
+                }
+                Some((name, value)) => set_alias(name, value),
+                None => match get_alias(arg) {
+                    Some(value) => print_line(&format_alias(arg, &value))?,
+                    None => {
+                        eprintln!("alias: {arg}: not found");
+                        status = 1;
+                    }
+                },
+            }
+        }
+
+        Ok(status)
+    })
+}
+
+/// The alias as it can be typed again to define it.
+fn format_alias(name: &str, value: &str) -> String {
+    format!("alias {name}='{}'", value.replace('\'', "'\\''"))
+}
+
+/// Anything the parser reads as a single plain word, without quotes nor expansions.
+fn is_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || "'\"\\$`=/|&;<>()".contains(c))
+}
+
+#[inline(always)]
+fn build_unalias_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        if args.first().is_some_and(|arg| arg == "-a") {
+            clear_aliases();
+            return Ok(0);
+        }
+        if args.is_empty() {
+            return Err(anyhow!("unalias: usage: unalias [-a] name [name ...]"));
+        }
+
+        let mut status = 0;
+        for name in args.iter() {
+            if !unset_alias(name) {
+                eprintln!("unalias: {name}: not found");
+                status = 1;
+            }
+        }
+
+        Ok(status)
+    })
+}
+
+#[inline(always)]
+fn build_source_exec(name: &str, args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    let name = name.to_owned();
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let Some((file, params)) = args.split_first() else {
+            return Err(anyhow!("{name}: filename argument required"));
+        };
+
+        // the arguments after the file are its positional parameters while it runs
+        let saved = (!params.is_empty()).then(|| set_positional_params(params.to_vec()));
+        let result = source(Path::new(file));
+        if let Some(saved) = saved {
+            set_positional_params(saved);
+        }
+
+        result.map_err(|e| anyhow!("{name}: {e}"))?;
+        Ok(get_last_status())
+    })
+}
+
+/// What a name runs as a command.
+enum CommandKind {
+    Alias(String),
+    Keyword,
+    Function(Arc<Command>),
+    Builtin,
+    File(PathBuf),
+}
+
+impl CommandKind {
+    /// The kind in a word, as `type -t` tells it.
+    fn word(&self) -> &'static str {
+        match self {
+            Self::Alias(_) => "alias",
+            Self::Keyword => "keyword",
+            Self::Function(_) => "function",
+            Self::Builtin => "builtin",
+            Self::File(_) => "file",
+        }
+    }
+}
+
+/// Everything the name can run, in the order the shell looks for them, so the first one is
+/// the one that runs. Without `all`, only that one. With `only_files`, the aliases, keywords,
+/// functions and builtins are left out.
+fn command_kinds(name: &str, all: bool, only_files: bool) -> Vec<CommandKind> {
+    let mut kinds = vec![];
+    if !only_files {
+        kinds.extend(get_alias(name).map(CommandKind::Alias));
+        if RESERVED_WORDS.contains(&name) {
+            kinds.push(CommandKind::Keyword);
+        }
+        kinds.extend(get_function(name).map(CommandKind::Function));
+        if BUILTINS.contains(&name) {
+            kinds.push(CommandKind::Builtin);
+        }
+    }
+
+    if name.contains('/') {
+        if cannot_execute(Path::new(name)).is_none() {
+            kinds.push(CommandKind::File(PathBuf::from(name)));
+        }
+    } else if all {
+        kinds.extend(hash::find_all(name).into_iter().map(CommandKind::File));
+    } else if kinds.is_empty() {
+        // the hashed one runs even if another one came before it in PATH since then
+        let path = hash::get(name).or_else(|| hash::find_all(name).into_iter().next());
+        kinds.extend(path.map(CommandKind::File));
+    }
+
+    if !all {
+        kinds.truncate(1);
+    }
+    kinds
+}
+
+/// How `type` and `command -V` tell what the name runs.
+fn describe_command(name: &str, kind: &CommandKind) -> String {
+    match kind {
+        CommandKind::Alias(value) => format!("{name} is aliased to `{value}'"),
+        CommandKind::Keyword => format!("{name} is a shell keyword"),
+        CommandKind::Function(body) => format!("{name} is a function\n{name}() {body}"),
+        CommandKind::Builtin => format!("{name} is a shell builtin"),
+        CommandKind::File(path) => format!("{name} is {}", path.display()),
+    }
+}
+
+#[inline(always)]
+fn build_type_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let (mut all, mut only_kind, mut only_path, mut only_files) = (false, false, false, false);
+        let mut names = args.iter().peekable();
+        while let Some(options) = names.next_if(|arg| arg.starts_with('-') && arg.len() > 1) {
+            for option in options.chars().skip(1) {
+                match option {
+                    'a' => all = true,
+                    't' => only_kind = true,
+                    'p' => only_path = true,
+                    'P' => only_files = true,
+                    _ => return Err(anyhow!("type: -{option}: invalid option")),
+                }
+            }
+        }
+
+        let mut status = 0;
+        for name in names {
+            let kinds = command_kinds(name, all, only_files);
+            if kinds.is_empty() {
+                // -t, -p and -P only tell with the status
+                if !only_kind && !only_path && !only_files {
+                    eprintln!("type: {name}: not found");
+                }
+                status = 1;
+            }
+
+            for kind in kinds.iter() {
+                let line = match kind {
+                    _ if only_kind => kind.word().to_owned(),
+                    CommandKind::File(path) if only_path || only_files => {
+                        path.to_string_lossy().into_owned()
+                    }
+                    _ if only_path => continue,
+                    kind => describe_command(name, kind),
+                };
+                print_line(&line)?;
+            }
+        }
+
+        Ok(status)
+    })
+}
+
+#[inline(always)]
+fn build_which_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let all = args.first().is_some_and(|arg| arg == "-a");
+        let names = if all { &args[1..] } else { &args[..] };
+
+        let mut status = 0;
+        for name in names.iter() {
+            let kinds = command_kinds(name, all, false);
+            if kinds.is_empty() {
+                eprintln!("{name} not found");
+                status = 1;
+            }
+
+            for kind in kinds.iter() {
+                print_line(&match kind {
+                    CommandKind::Alias(value) => format!("{name}: aliased to {value}"),
+                    CommandKind::Keyword => format!("{name}: shell reserved word"),
+                    CommandKind::Function(body) => format!("{name}() {body}"),
+                    CommandKind::Builtin => format!("{name}: shell built-in command"),
+                    CommandKind::File(path) => path.to_string_lossy().into_owned(),
+                })?;
+            }
         }
 
-        Ok(())
+        Ok(status)
     })
 }
+
+#[inline(always)]
+fn build_command_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let verbose = match args.first().map(String::as_str) {
+            // without a name there's nothing to run
+            None => return Ok(0),
+            Some("-v") => false,
+            Some("-V") => true,
+            _ => return Err(anyhow!("command: usage: command [-v | -V] name [arg ...]")),
+        };
+
+        let mut status = 0;
+        for name in args[1..].iter() {
+            let Some(kind) = command_kinds(name, false, false).pop() else {
+                if verbose {
+                    eprintln!("command: {name}: not found");
+                }
+                status = 1;
+                continue;
+            };
+
+            print_line(&match kind {
+                _ if verbose => describe_command(name, &kind),
+                CommandKind::Alias(value) => format_alias(name, &value),
+                CommandKind::File(path) => path.to_string_lossy().into_owned(),
+                _ => name.clone(),
+            })?;
+        }
+
+        Ok(status)
+    })
+}
+
+/// What `builtin` runs for a name that isn't one of the builtins.
+#[inline(always)]
+fn build_not_builtin_exec(name: &str) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    let name = name.to_owned();
+    Box::new(move || Err(anyhow!("builtin: {name}: not a shell builtin")))
+}
+
+#[inline(always)]
+fn build_break_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let levels = loop_levels("break", &args)?;
+        leave_loops(levels, false).map_err(|e| anyhow!("break: {e}"))?;
+        Ok(0)
+    })
+}
+
+#[inline(always)]
+fn build_continue_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let levels = loop_levels("continue", &args)?;
+        leave_loops(levels, true).map_err(|e| anyhow!("continue: {e}"))?;
+        Ok(0)
+    })
+}
+
+/// The `n` of `break [n]` and `continue [n]`, how many loops they leave. 1 by default.
+fn loop_levels(name: &str, args: &[String]) -> Result<usize> {
+    match args {
+        [] => Ok(1),
+        [arg] => match arg.parse::<usize>() {
+            Ok(0) => Err(anyhow!("{name}: {arg}: loop count out of range")),
+            Ok(levels) => Ok(levels),
+            Err(_) => Err(anyhow!("{name}: {arg}: numeric argument required")),
+        },
+        _ => Err(anyhow!("{name}: too many arguments")),
+    }
+}
+
+#[inline(always)]
+fn build_complete_exec(args: &[String]) -> Box<dyn FnOnce() -> Result<i32> + Send> {
+    // TODO: Refactor to not clone args.
+    let args = args.to_owned();
+    Box::new(move || {
+        let mut spec = CompletionSpec::default();
+        let mut print = args.is_empty();
+        let mut remove = false;
+
+        let mut args = args.iter();
+        let mut names = vec![];
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-W" => match args.next() {
+                    Some(words) => spec
+                        .words
+                        .extend(words.split_whitespace().map(str::to_owned)),
+                    None => return Err(anyhow!("complete: -W: option requires an argument")),
+                },
+                "-f" => spec.files = true,
+                "-d" => spec.directories = true,
+                "-c" => spec.commands = true,
+                "-v" => spec.variables = true,
+                "-p" => print = true,
+                "-r" => remove = true,
+                _ if arg.starts_with('-') => {
+                    return Err(anyhow!("complete: {arg}: invalid option"));
+                }
+                _ => names.push(arg.clone()),
+            }
+        }
+
+        if print || remove {
+            let specs = completion::specs();
+            // with no names, every one of them
+            let names = if names.is_empty() {
+                specs.iter().map(|(name, _)| name.clone()).collect()
+            } else {
+                names
+            };
+
+            let mut status = 0;
+            for name in names {
+                let Some((_, spec)) = specs.iter().find(|(command, _)| *command == name) else {
+                    eprintln!("complete: {name}: no completion specification");
+                    status = 1;
+                    continue;
+                };
+                if remove {
+                    completion::unregister(&name);
+                } else {
+                    let options = spec.to_string();
+                    let options = if options.is_empty() {
+                        options
+                    } else {
+                        options + " "
+                    };
+                    print_line(&format!("complete {options}{name}"))?;
+                }
+            }
+            return Ok(status);
+        }
+
+        if names.is_empty() {
+            return Err(anyhow!("complete: a command name is needed"));
+        }
+        for name in names {
+            completion::register(&name, spec.clone());
+        }
+
+        Ok(0)
+    })
+}
+
+/// Terminates a forked child of the shell that ran shell code, like a builtin in background
+/// or a pipeline stage.
+pub fn exit_forked_child(status: i32) -> ! {
+    // SAFETY:
+    // Builtins write through the buffered STDOUT, so flush it before leaving, cause
+    // libc::_exit does not run any Rust destructor nor flushes Rust buffers.
+    if let Ok(stdout) = STDOUT.try_lock() {
+        let _ = stdout.borrow_mut().flush();
+    }
+    unsafe { libc::_exit(status) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| String::from(arg)).collect()
+    }
+
+    #[test]
+    fn defines_and_removes_aliases() {
+        // the aliases are shared by the tests running at the same time, so the names are not
+        let status = build_alias_exec(&args(&["tsh_test_g=git", "tsh_test_gs=git status "]))();
+        assert_eq!(status.unwrap(), 0);
+        assert_eq!(get_alias("tsh_test_g").as_deref(), Some("git"));
+        assert_eq!(get_alias("tsh_test_gs").as_deref(), Some("git status "));
+
+        // the valid ones are still defined
+        let status = build_alias_exec(&args(&["tsh_test/x=y", "tsh_test_h=hg", "tsh_test_none"]))();
+        assert_eq!(status.unwrap(), 1);
+        assert_eq!(get_alias("tsh_test/x"), None);
+        assert_eq!(get_alias("tsh_test_h").as_deref(), Some("hg"));
+
+        let status = build_unalias_exec(&args(&["tsh_test_g", "tsh_test_none"]))();
+        assert_eq!(status.unwrap(), 1);
+        assert_eq!(get_alias("tsh_test_g"), None);
+        assert_eq!(get_alias("tsh_test_gs").as_deref(), Some("git status "));
+
+        assert!(build_unalias_exec(&[])().is_err());
+    }
+
+    #[test]
+    fn writes_the_aliases_back_as_they_are_typed() {
+        assert_eq!(format_alias("l", "ls -l"), "alias l='ls -l'");
+        assert_eq!(
+            format_alias("say", "echo it's"),
+            "alias say='echo it'\\''s'"
+        );
+
+        assert!(is_alias_name("ll"));
+        assert!(is_alias_name("git-st."));
+        for name in ["", "a b", "a/b", "$a", "a;b", "'a'"] {
+            assert!(!is_alias_name(name), "{name}");
+        }
+    }
+}
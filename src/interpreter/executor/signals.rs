@@ -0,0 +1,193 @@
+use std::{
+    os::fd::IntoRawFd,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{FcntlArg, OFlag, fcntl},
+    libc,
+    sys::{
+        signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction, signal},
+        wait::WaitStatus,
+    },
+    unistd::{Pid, pipe2},
+};
+
+// The children reaped by the SIGCHLD handler wait here until the shell looks at them. The
+// handler can't take any lock, so this is a ring of atomics: the handler is the only one
+// writing (REAPING makes sure of that) and the shell the only one reading.
+const QUEUE_LEN: usize = 128;
+static QUEUE_PIDS: [AtomicI32; QUEUE_LEN] = [const { AtomicI32::new(0) }; QUEUE_LEN];
+static QUEUE_STATUSES: [AtomicI32; QUEUE_LEN] = [const { AtomicI32::new(0) }; QUEUE_LEN];
+static QUEUE_HEAD: AtomicUsize = AtomicUsize::new(0);
+static QUEUE_TAIL: AtomicUsize = AtomicUsize::new(0);
+
+static REAPING: AtomicBool = AtomicBool::new(false);
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+// The handler writes a byte for every child it reaps, so the shell can sleep on the read end
+// until some child changes
+static WAKE_READ_FD: AtomicI32 = AtomicI32::new(-1);
+static WAKE_WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Installs the SIGCHLD handler. An interactive shell also ignores the signals the terminal
+/// sends on Ctrl-C, Ctrl-\ and Ctrl-Z, those are meant for the job in foreground only.
+pub fn init(interactive: bool) {
+    open_wake_pipe();
+
+    let action = SigAction::new(
+        SigHandler::Handler(handle_sigchld),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    // SAFETY:
+    // The handler only calls async-signal-safe functions (waitpid and write) and touches
+    // nothing but atomics.
+    let _ = unsafe { sigaction(Signal::SIGCHLD, &action) };
+
+    if interactive {
+        for ignored in [Signal::SIGINT, Signal::SIGQUIT, Signal::SIGTSTP] {
+            // SAFETY:
+            // Ignoring a signal does not install any handler.
+            let _ = unsafe { signal(ignored, SigHandler::SigIgn) };
+        }
+    }
+}
+
+/// Gives a forked child the default behaviour for the signals. The SIGCHLD handler stays, a
+/// child running shell code waits for its own children the same way, but it needs its own
+/// queue and wake up pipe. On execve the handler goes away by itself.
+pub fn reset_child() {
+    for signal_to_reset in [Signal::SIGINT, Signal::SIGQUIT, Signal::SIGTSTP] {
+        // SAFETY:
+        // The default disposition does not install any handler.
+        let _ = unsafe { signal(signal_to_reset, SigHandler::SigDfl) };
+    }
+
+    // Whatever the parent left in the queue belongs to the parent
+    QUEUE_TAIL.store(QUEUE_HEAD.load(Ordering::Acquire), Ordering::Release);
+    for fd in [&WAKE_READ_FD, &WAKE_WRITE_FD] {
+        // SAFETY:
+        // The fds of the parent pipe are only used through these atomics, that are being
+        // replaced right after.
+        unsafe { libc::close(fd.load(Ordering::Relaxed)) };
+    }
+    open_wake_pipe();
+}
+
+fn open_wake_pipe() {
+    // Close on exec, the commands have nothing to do with it
+    let Ok((read, write)) = pipe2(OFlag::O_CLOEXEC) else {
+        return;
+    };
+    // The handler must never block, a full pipe already wakes up anyone
+    let _ = fcntl(&write, FcntlArg::F_SETFL(OFlag::O_NONBLOCK));
+
+    WAKE_READ_FD.store(read.into_raw_fd(), Ordering::Relaxed);
+    WAKE_WRITE_FD.store(write.into_raw_fd(), Ordering::Relaxed);
+}
+
+extern "C" fn handle_sigchld(_: libc::c_int) {
+    // The interrupted code may be about to read errno
+    let errno = Errno::last_raw();
+    reap();
+    Errno::set_raw(errno);
+}
+
+/// Reaps every child that changed into the queue, as long as there is room. Runs on the
+/// handler and on the shell, only one at a time does the job: if someone is already at it,
+/// it's told to look again before leaving.
+fn reap() {
+    loop {
+        PENDING.store(true, Ordering::SeqCst);
+        if REAPING.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        PENDING.store(false, Ordering::SeqCst);
+
+        loop {
+            let head = QUEUE_HEAD.load(Ordering::Relaxed);
+            if head.wrapping_sub(QUEUE_TAIL.load(Ordering::Acquire)) >= QUEUE_LEN {
+                // Full, the rest stay as zombies until the shell makes room
+                break;
+            }
+
+            let mut status = 0;
+            // SAFETY:
+            // waitpid is async-signal-safe and the status is a local.
+            let pid = unsafe {
+                libc::waitpid(
+                    -1,
+                    &mut status,
+                    libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED,
+                )
+            };
+            if pid <= 0 {
+                break;
+            }
+
+            QUEUE_PIDS[head % QUEUE_LEN].store(pid, Ordering::Relaxed);
+            QUEUE_STATUSES[head % QUEUE_LEN].store(status, Ordering::Relaxed);
+            QUEUE_HEAD.store(head.wrapping_add(1), Ordering::Release);
+
+            // SAFETY:
+            // write is async-signal-safe, and the pipe never blocks. If it's full, there are
+            // already enough bytes to wake up anyone.
+            unsafe {
+                libc::write(
+                    WAKE_WRITE_FD.load(Ordering::Relaxed),
+                    [0u8].as_ptr().cast(),
+                    1,
+                )
+            };
+        }
+
+        REAPING.store(false, Ordering::SeqCst);
+        if !PENDING.load(Ordering::SeqCst) {
+            return;
+        }
+    }
+}
+
+/// Takes every change of the children since the last call.
+pub fn take_reaped() -> Vec<WaitStatus> {
+    let mut statuses = vec![];
+
+    loop {
+        let tail = QUEUE_TAIL.load(Ordering::Relaxed);
+        if tail == QUEUE_HEAD.load(Ordering::Acquire) {
+            // Now there is room for what the handler may have left behind
+            reap();
+            if tail == QUEUE_HEAD.load(Ordering::Acquire) {
+                break;
+            }
+            continue;
+        }
+
+        let pid = QUEUE_PIDS[tail % QUEUE_LEN].load(Ordering::Relaxed);
+        let status = QUEUE_STATUSES[tail % QUEUE_LEN].load(Ordering::Relaxed);
+        QUEUE_TAIL.store(tail.wrapping_add(1), Ordering::Release);
+
+        if let Ok(status) = WaitStatus::from_raw(Pid::from_raw(pid), status) {
+            statuses.push(status);
+        }
+    }
+
+    statuses
+}
+
+/// Sleeps until some child changes, or returns right away if one did since the last call.
+/// Spurious wake ups are possible, the caller must check again what it is waiting for.
+pub fn wait_child() {
+    let mut byte = [0u8];
+    // SAFETY:
+    // The buffer is a local with room for the single byte asked.
+    unsafe {
+        libc::read(
+            WAKE_READ_FD.load(Ordering::Relaxed),
+            byte.as_mut_ptr().cast(),
+            1,
+        )
+    };
+}
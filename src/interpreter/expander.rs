@@ -0,0 +1,845 @@
+use std::{
+    fmt::Display,
+    iter::Peekable,
+    mem,
+    str::Chars,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use anyhow::{Result, anyhow};
+use nix::unistd::{User, getuid};
+
+use crate::interpreter::{
+    arithmetic::evaluate,
+    environment::{
+        get_last_background_pid, get_last_status, get_positional_params, get_shell_name,
+        get_shell_pid, get_var, is_option_set, is_valid_name, set_var,
+    },
+    executor::substitute,
+    parser::{read_backquoted, read_substitution},
+    pattern,
+};
+
+// The most elements a sequence like `{1..10}` gives, a longer one is left as it is instead of
+// filling the memory
+const MAX_BRACE_SEQUENCE: u64 = 100_000;
+
+// The status of the last command substitution, -1 if none ran since the last time it was
+// taken. A command made only of assignments ends with it.
+static SUBSTITUTION_STATUS: AtomicI32 = AtomicI32::new(-1);
+
+pub fn take_substitution_status() -> Option<i32> {
+    let status = SUBSTITUTION_STATUS.swap(-1, Ordering::Relaxed);
+    (status >= 0).then_some(status)
+}
+
+/// The error of `${name?message}` on a parameter that isn't set. A shell that isn't
+/// interactive can't go on without it and exits.
+#[derive(Debug)]
+pub struct UnsetParameter {
+    name: String,
+    message: String,
+}
+
+impl Display for UnsetParameter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)
+    }
+}
+
+impl std::error::Error for UnsetParameter {}
+
+/// Expands the words of a command. The result may have more or less fields than the words,
+/// cause unquoted expansions are split and the ones that expand to nothing vanish. Braces go
+/// first and the pathnames last, once the fields are known.
+pub fn expand_words(words: &[String]) -> Result<Vec<String>> {
+    let mut fields = Fields::new(true);
+    for word in words.iter().flat_map(|word| expand_braces(word)) {
+        // "$@" without parameters is no field at all, not an empty one
+        if matches!(word.as_str(), "\"$@\"" | "\"${@}\"") && get_positional_params().is_empty() {
+            continue;
+        }
+
+        fields.expand(&word, Context::Unquoted)?;
+        fields.end_field();
+    }
+
+    let mut expanded = vec![];
+    for (field, pattern) in fields.fields.into_iter().zip(fields.patterns) {
+        let Some(pattern) = pattern else {
+            expanded.push(field);
+            continue;
+        };
+
+        let paths = pattern::expand_path(&pattern, is_option_set("dotglob"));
+        if !paths.is_empty() {
+            expanded.extend(paths);
+        } else if is_option_set("failglob") {
+            return Err(anyhow!("no match: {field}"));
+        } else if !is_option_set("nullglob") {
+            // without matches the pattern stays as it is
+            expanded.push(field);
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// Expands a word that must stay as a single one, like the value of an assignment or the
+/// target of a redirect.
+pub fn expand_word(word: &str) -> Result<String> {
+    let mut fields = Fields::new(false);
+    fields.expand(word, Context::Unquoted)?;
+
+    Ok(fields.current)
+}
+
+/// Expands the value of an assignment, a single word too, but where a `~` can also follow
+/// each `:`, as in `PATH=~/bin:~/.local/bin`.
+pub fn expand_assignment(value: &str) -> Result<String> {
+    let mut fields = Fields::new(false);
+    fields.assignment = true;
+    fields.expand(value, Context::Unquoted)?;
+
+    Ok(fields.current)
+}
+
+/// Expands a word to match against it, like a pattern of `case`. What was quoted is escaped,
+/// so it only matches itself.
+pub fn expand_pattern(word: &str) -> Result<String> {
+    let mut fields = Fields::new(false);
+    fields.expand(word, Context::Unquoted)?;
+
+    Ok(fields.pattern)
+}
+
+/// Expands the body of a here-document, where quotes have no special meaning but the
+/// parameters are still expanded.
+pub fn expand_here_document(body: &str) -> Result<String> {
+    let mut fields = Fields::new(false);
+    fields.expand(body, Context::HereDocument)?;
+
+    Ok(fields.current)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Context {
+    Unquoted,
+    DoubleQuoted,
+    HereDocument,
+}
+
+struct Fields {
+    fields: Vec<String>,
+    // every field as a pattern for the pathname expansion, if it has unquoted `*`, `?` or `[`
+    patterns: Vec<Option<String>>,
+    current: String,
+    // the current field with what was quoted escaped, and if it's a pattern at all
+    pattern: String,
+    globbing: bool,
+    // "" is an empty field, but an unquoted expansion to nothing is no field at all
+    has_current: bool,
+    // the last field was ended by IFS blanks, a separator right after them goes with them
+    blank_ended: bool,
+    split: bool,
+    // in an assignment, a tilde prefix can also start after a colon
+    assignment: bool,
+}
+
+impl Fields {
+    fn new(split: bool) -> Self {
+        Self {
+            fields: vec![],
+            patterns: vec![],
+            current: String::new(),
+            pattern: String::new(),
+            globbing: false,
+            has_current: false,
+            blank_ended: false,
+            split,
+            assignment: false,
+        }
+    }
+
+    fn push_literal(&mut self, text: &str) {
+        for c in text.chars() {
+            self.push_char(c, true);
+        }
+        self.has_current = true;
+    }
+
+    /// Adds a char to the current field. Only the unquoted ones keep their meaning in a
+    /// pattern, the backslashes coming from an expansion never do.
+    fn push_char(&mut self, c: char, quoted: bool) {
+        self.current.push(c);
+        if c == '\\' || (quoted && matches!(c, '*' | '?' | '[' | ']')) {
+            self.pattern.push('\\');
+        }
+        self.pattern.push(c);
+        self.globbing |= !quoted && matches!(c, '*' | '?' | '[');
+        self.has_current = true;
+        self.blank_ended = false;
+    }
+
+    fn push_expanded(&mut self, text: &str, context: Context) {
+        if context != Context::Unquoted {
+            self.push_literal(text);
+            return;
+        }
+        if !self.split {
+            // not split, but still a pattern when unquoted
+            for c in text.chars() {
+                self.push_char(c, false);
+            }
+            self.has_current = true;
+            return;
+        }
+
+        // The blanks of IFS end a field, as many as there are, any other separator ends one
+        // even if it's empty: with IFS=: `a::b` is `a`, `` and `b`
+        let ifs = get_var("IFS").unwrap_or_else(|| String::from(" \t\n"));
+        for c in text.chars() {
+            if !ifs.contains(c) {
+                self.push_char(c, false);
+            } else if matches!(c, ' ' | '\t' | '\n') {
+                if self.has_current {
+                    self.end_field();
+                    self.blank_ended = true;
+                }
+            } else if !mem::take(&mut self.blank_ended) {
+                self.has_current = true;
+                self.end_field();
+            }
+        }
+    }
+
+    /// "$@" gives every positional parameter as a field of its own, the text around it
+    /// sticks to the first and the last ones.
+    fn push_params(&mut self) {
+        for (i, param) in get_positional_params().iter().enumerate() {
+            if i > 0 {
+                self.end_field();
+            }
+            self.push_literal(param);
+        }
+    }
+
+    fn end_field(&mut self) {
+        if self.has_current {
+            self.fields.push(mem::take(&mut self.current));
+            let pattern = mem::take(&mut self.pattern);
+            self.patterns
+                .push(mem::take(&mut self.globbing).then_some(pattern));
+            self.has_current = false;
+        }
+        self.blank_ended = false;
+    }
+
+    fn expand(&mut self, word: &str, mut context: Context) -> Result<()> {
+        let mut chars = word.chars().peekable();
+        // a tilde prefix can only be at the start
+        let mut tilde_allowed = context == Context::Unquoted;
+
+        while let Some(c) = chars.next() {
+            let at_tilde_prefix = mem::take(&mut tilde_allowed);
+            match c {
+                '~' if at_tilde_prefix => self.expand_tilde(&mut chars),
+                ':' if self.assignment && context == Context::Unquoted => {
+                    self.push_char(c, false);
+                    tilde_allowed = true;
+                }
+                '\'' if context == Context::Unquoted => {
+                    self.push_literal("");
+                    for c in chars.by_ref().take_while(|&c| c != '\'') {
+                        self.push_char(c, true);
+                    }
+                }
+                '"' if context != Context::HereDocument => {
+                    self.push_literal("");
+                    context = match context {
+                        Context::DoubleQuoted => Context::Unquoted,
+                        _ => Context::DoubleQuoted,
+                    };
+                }
+                '\\' => match chars.peek() {
+                    // an escaped newline just continues the line
+                    Some('\n') => {
+                        chars.next();
+                    }
+                    Some(&escaped)
+                        if context == Context::Unquoted
+                            || matches!(escaped, '$' | '`' | '\\')
+                            || (escaped == '"' && context == Context::DoubleQuoted) =>
+                    {
+                        chars.next();
+                        self.push_char(escaped, true);
+                    }
+                    _ => self.push_literal("\\"),
+                },
+                '$' => self.expand_dollar(&mut chars, context)?,
+                '`' => {
+                    // inside, a backslash only escapes what it escapes in double quotes
+                    let backquoted = read_backquoted(&mut chars)?;
+                    let mut backquoted = backquoted.chars().peekable();
+                    let mut command = String::new();
+                    while let Some(c) = backquoted.next() {
+                        if c == '\\'
+                            && let Some(next) =
+                                backquoted.next_if(|c| matches!(c, '$' | '`' | '\\'))
+                        {
+                            command.push(next);
+                        } else {
+                            command.push(c);
+                        }
+                    }
+                    self.push_substitution(&command, context)?;
+                }
+                _ => self.push_char(c, context != Context::Unquoted),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `~` is the home directory, `~user` the one of the user, `~+` the current directory and
+    /// `~-` the previous one. The prefix goes up to the first `/`, anything quoted in it means
+    /// it's just a tilde.
+    fn expand_tilde(&mut self, chars: &mut Peekable<Chars>) {
+        let mut ahead = chars.clone();
+        let mut prefix = String::new();
+        while let Some(c) = ahead.next_if(|&c| c != '/' && !(self.assignment && c == ':')) {
+            if matches!(c, '\'' | '"' | '\\' | '$' | '`') {
+                self.push_char('~', false);
+                return;
+            }
+            prefix.push(c);
+        }
+
+        match tilde_directory(&prefix) {
+            Some(dir) => {
+                self.push_literal(&dir);
+                *chars = ahead;
+            }
+            None => self.push_char('~', false),
+        }
+    }
+
+    fn push_substitution(&mut self, command: &str, context: Context) -> Result<()> {
+        let output = substitute(command)?;
+        SUBSTITUTION_STATUS.store(get_last_status(), Ordering::Relaxed);
+        self.push_expanded(&output, context);
+        Ok(())
+    }
+
+    fn expand_dollar(&mut self, chars: &mut Peekable<Chars>, context: Context) -> Result<()> {
+        match chars.peek() {
+            Some('(') => {
+                chars.next();
+                let command = read_substitution(chars)?;
+                match arithmetic_expression(&command) {
+                    Some(expression) => {
+                        // the parameters and substitutions inside go first
+                        let value = evaluate(&expand_word(expression)?)?;
+                        self.push_expanded(&value.to_string(), context);
+                        Ok(())
+                    }
+                    None => self.push_substitution(&command, context),
+                }
+            }
+            Some('{') => {
+                chars.next();
+                let inner = read_braced(chars)?;
+                self.expand_braced(&inner, context)
+            }
+            Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|&c| c.is_ascii_alphanumeric() || c == '_') {
+                    name.push(c);
+                }
+
+                let value = get_parameter(&name).unwrap_or_default();
+                self.push_expanded(&value, context);
+                Ok(())
+            }
+            Some('@') if context == Context::DoubleQuoted && self.split => {
+                chars.next();
+                self.push_params();
+                Ok(())
+            }
+            Some(&c) if is_special_parameter(c) => {
+                chars.next();
+                let value = get_parameter(&c.to_string()).unwrap_or_default();
+                self.push_expanded(&value, context);
+                Ok(())
+            }
+            // a lonely $ is just a $
+            _ => {
+                self.push_literal("$");
+                Ok(())
+            }
+        }
+    }
+
+    fn expand_braced(&mut self, inner: &str, context: Context) -> Result<()> {
+        // ${#name}
+        if let Some(name) = inner.strip_prefix('#')
+            && parameter_name_len(name) == name.len()
+            && !name.is_empty()
+        {
+            let len = get_parameter(name).unwrap_or_default().chars().count();
+            self.push_expanded(&len.to_string(), context);
+            return Ok(());
+        }
+
+        let name_len = parameter_name_len(inner);
+        if name_len == 0 {
+            return Err(anyhow!("${{{inner}}}: bad substitution"));
+        }
+
+        let (name, operation) = inner.split_at(name_len);
+        if name == "@" && operation.is_empty() && context == Context::DoubleQuoted && self.split {
+            self.push_params();
+            return Ok(());
+        }
+
+        let value = get_parameter(name);
+        if operation.is_empty() {
+            self.push_expanded(&value.unwrap_or_default(), context);
+            return Ok(());
+        }
+
+        let Some((operator, word)) = [
+            ":-", ":=", ":+", ":?", "-", "=", "+", "?", "%%", "%", "##", "#",
+        ]
+        .iter()
+        .find_map(|operator| Some((*operator, operation.strip_prefix(operator)?))) else {
+            return Err(anyhow!("${{{inner}}}: bad substitution"));
+        };
+
+        // With the colon, an empty value counts as unset
+        let unset = match &value {
+            Some(value) => operator.starts_with(':') && value.is_empty(),
+            None => true,
+        };
+
+        match operator {
+            ":-" | "-" => {
+                if unset {
+                    self.expand(word, context)?;
+                } else {
+                    self.push_expanded(&value.unwrap_or_default(), context);
+                }
+            }
+            ":=" | "=" => {
+                let value = if unset {
+                    if !is_valid_name(name) {
+                        return Err(anyhow!("${name}: cannot assign in this way"));
+                    }
+
+                    let mut default = Fields::new(false);
+                    default.expand(word, context)?;
+                    set_var(name, &default.current);
+                    default.current
+                } else {
+                    value.unwrap_or_default()
+                };
+                self.push_expanded(&value, context);
+            }
+            ":+" | "+" => {
+                if !unset {
+                    self.expand(word, context)?;
+                }
+            }
+            ":?" | "?" => {
+                if unset {
+                    let message = match expand_word(word)? {
+                        message if message.is_empty() => String::from("parameter null or not set"),
+                        message => message,
+                    };
+                    let name = name.to_owned();
+                    return Err(UnsetParameter { name, message }.into());
+                }
+                self.push_expanded(&value.unwrap_or_default(), context);
+            }
+            _ => {
+                let value = value.unwrap_or_default();
+                let pattern = expand_pattern(word)?;
+                let value = match operator {
+                    "%%" => remove_suffix(&value, &pattern, true),
+                    "%" => remove_suffix(&value, &pattern, false),
+                    "##" => remove_prefix(&value, &pattern, true),
+                    _ => remove_prefix(&value, &pattern, false),
+                };
+                self.push_expanded(value, context);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn tilde_directory(prefix: &str) -> Option<String> {
+    let user = match prefix {
+        "" => match get_var("HOME") {
+            Some(home) => return Some(home),
+            None => User::from_uid(getuid()),
+        },
+        "+" => return get_var("PWD"),
+        "-" => return get_var("OLDPWD"),
+        name => User::from_name(name),
+    };
+
+    user.ok()
+        .flatten()
+        .map(|user| user.dir.to_string_lossy().into_owned())
+}
+
+/// Brace expansion, `a{b,c}d` is `abd acd` and `{1..3}` is `1 2 3`. The braces inside quotes
+/// and the ones of a `${` are left alone.
+fn expand_braces(word: &str) -> Vec<String> {
+    let chars = word.chars().collect::<Vec<_>>();
+    let mut quote = None;
+
+    let mut i = 0;
+    while i < chars.len() {
+        match (quote, chars[i]) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => i += 1,
+            (Some(_), _) => {}
+            (None, c @ ('\'' | '"')) => quote = Some(c),
+            (None, '\\') => i += 1,
+            (None, '$') if chars.get(i + 1) == Some(&'{') => {
+                // to the brace that closes it
+                let mut depth = 0;
+                while let Some(&c) = chars.get(i) {
+                    match c {
+                        '{' => depth += 1,
+                        '}' if depth == 1 => break,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    i += 1;
+                }
+            }
+            (None, '$') if chars.get(i + 1) == Some(&'(') => {
+                // a command substitution expands its own braces
+                let mut depth = 0;
+                while let Some(&c) = chars.get(i) {
+                    match c {
+                        '(' => depth += 1,
+                        ')' if depth == 1 => break,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    i += 1;
+                }
+            }
+            (None, '`') => {
+                i += 1;
+                while chars.get(i).is_some_and(|&c| c != '`') {
+                    i += usize::from(chars[i] == '\\') + 1;
+                }
+            }
+            (None, '{') => {
+                if let Some((close, alternatives)) = brace_alternatives(&chars, i) {
+                    let prefix = chars[..i].iter().collect::<String>();
+                    let suffix = chars[close + 1..].iter().collect::<String>();
+                    // the alternatives and the rest of the word may have more braces
+                    return alternatives
+                        .iter()
+                        .flat_map(|alternative| {
+                            expand_braces(&format!("{prefix}{alternative}{suffix}"))
+                        })
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    vec![word.to_owned()]
+}
+
+/// What the braces opening at `open` expand to, and where they close. Braces without a comma
+/// nor a sequence inside are just braces.
+fn brace_alternatives(chars: &[char], open: usize) -> Option<(usize, Vec<String>)> {
+    let mut quote = None;
+    let mut depth = 0;
+    let mut commas = vec![];
+
+    let mut close = open;
+    loop {
+        match (quote, *chars.get(close)?) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => close += 1,
+            (Some(_), _) => {}
+            (None, c @ ('\'' | '"')) => quote = Some(c),
+            (None, '\\') => close += 1,
+            (None, '{') => depth += 1,
+            (None, '}') if depth == 1 => break,
+            (None, '}') => depth -= 1,
+            (None, ',') if depth == 1 => commas.push(close),
+            _ => {}
+        }
+        close += 1;
+    }
+
+    if commas.is_empty() {
+        let inner = chars[open + 1..close].iter().collect::<String>();
+        return Some((close, brace_sequence(&inner)?));
+    }
+
+    let mut alternatives = vec![];
+    let mut start = open + 1;
+    for end in commas.into_iter().chain([close]) {
+        alternatives.push(chars[start..end].iter().collect());
+        start = end + 1;
+    }
+
+    Some((close, alternatives))
+}
+
+/// `1..10`, `10..1..2` or `a..e`. The numbers are padded with zeros to the same width when
+/// one of the ends is. More than `MAX_BRACE_SEQUENCE` elements is no sequence.
+fn brace_sequence(inner: &str) -> Option<Vec<String>> {
+    let (start, end, step) = match inner.split("..").collect::<Vec<_>>()[..] {
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step.parse::<i64>().ok()?.unsigned_abs().max(1)),
+        _ => return None,
+    };
+    let step = usize::try_from(step).ok()?;
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = [start, end].iter().any(|n| {
+            let digits = n.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        });
+        let width = if padded {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        if first.abs_diff(last) / step as u64 >= MAX_BRACE_SEQUENCE {
+            return None;
+        }
+
+        let numbers = if first <= last {
+            (first..=last).step_by(step).collect::<Vec<_>>()
+        } else {
+            (last..=first).rev().step_by(step).collect()
+        };
+        return Some(numbers.iter().map(|n| format!("{n:0width$}")).collect());
+    }
+
+    let (mut start_chars, mut end_chars) = (start.chars(), end.chars());
+    let (Some(first), None, Some(last), None) = (
+        start_chars.next(),
+        start_chars.next(),
+        end_chars.next(),
+        end_chars.next(),
+    ) else {
+        return None;
+    };
+    let (first, last) = (first as u32, last as u32);
+    if u64::from(first.abs_diff(last)) / step as u64 >= MAX_BRACE_SEQUENCE {
+        return None;
+    }
+    let codes = if first <= last {
+        (first..=last).step_by(step).collect::<Vec<_>>()
+    } else {
+        (last..=first).rev().step_by(step).collect()
+    };
+
+    Some(
+        codes
+            .into_iter()
+            .filter_map(char::from_u32)
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// The expression of a `$((expression))`, when what the `$(` has inside is all in parentheses.
+/// Otherwise it's a command substitution that happens to start with a subshell.
+fn arithmetic_expression(inner: &str) -> Option<&str> {
+    let expression = inner.strip_prefix('(')?.strip_suffix(')')?;
+
+    let mut depth = 0usize;
+    for c in expression.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.checked_sub(1)?,
+            _ => {}
+        }
+    }
+
+    Some(expression)
+}
+
+/// Reads until the `}` that closes a `${`, the opening brace must be already consumed.
+fn read_braced(chars: &mut Peekable<Chars>) -> Result<String> {
+    let mut inner = String::new();
+    let mut depth = 1;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                inner.push(c);
+                if let Some(escaped) = chars.next() {
+                    inner.push(escaped);
+                }
+                continue;
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                inner.push(c);
+                inner.push('{');
+                chars.next();
+                depth += 1;
+                continue;
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(inner);
+                }
+            }
+            _ => {}
+        }
+        inner.push(c);
+    }
+
+    Err(anyhow!("${{{inner}: bad substitution"))
+}
+
+/// The parameters named by a single char, `$1` to `$9` included.
+fn is_special_parameter(c: char) -> bool {
+    matches!(c, '$' | '?' | '!' | '#' | '@' | '*') || c.is_ascii_digit()
+}
+
+/// The length of the parameter name at the start of the text, a special parameter takes a
+/// single char. Only inside braces, so `${10}` is the tenth positional parameter.
+fn parameter_name_len(text: &str) -> usize {
+    match text.chars().next() {
+        Some(c) if c.is_ascii_digit() => text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len()),
+        Some(c) if is_special_parameter(c) => 1,
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => text
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(text.len()),
+        _ => 0,
+    }
+}
+
+fn get_parameter(name: &str) -> Option<String> {
+    match name {
+        "$" => Some(get_shell_pid().to_string()),
+        "?" => Some(get_last_status().to_string()),
+        "!" => get_last_background_pid().map(|pid| pid.to_string()),
+        "#" => Some(get_positional_params().len().to_string()),
+        "0" => Some(get_shell_name()),
+        // Joined by the first char of IFS, when unquoted they are split again anyway
+        "@" | "*" => {
+            let separator = get_var("IFS").map_or(Some(' '), |ifs| ifs.chars().next());
+            Some(get_positional_params().join(&separator.map(String::from).unwrap_or_default()))
+        }
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => {
+            let index = name.parse::<usize>().ok()?;
+            get_positional_params().get(index.checked_sub(1)?).cloned()
+        }
+        _ => get_var(name),
+    }
+}
+
+fn remove_prefix<'a>(value: &'a str, pattern: &str, longest: bool) -> &'a str {
+    let mut ends = value
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([value.len()])
+        .collect::<Vec<_>>();
+    if longest {
+        ends.reverse();
+    }
+
+    ends.into_iter()
+        .find(|&end| pattern::matches(pattern, &value[..end]))
+        .map_or(value, |end| &value[end..])
+}
+
+fn remove_suffix<'a>(value: &'a str, pattern: &str, longest: bool) -> &'a str {
+    let mut starts = value
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([value.len()])
+        .collect::<Vec<_>>();
+    if !longest {
+        starts.reverse();
+    }
+
+    starts
+        .into_iter()
+        .find(|&start| pattern::matches(pattern, &value[start..]))
+        .map_or(value, |start| &value[..start])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_the_unquoted_expansions_by_ifs() {
+        let split = |ifs: &str, value: &str| {
+            set_var("IFS", ifs);
+            set_var("TSH_TEST_SPLIT", value);
+            expand_words(&["$TSH_TEST_SPLIT".to_owned()]).unwrap()
+        };
+
+        assert_eq!(split(" \t\n", "  a \t b\n"), ["a", "b"]);
+        assert_eq!(split(":", "a::b"), ["a", "", "b"]);
+        assert_eq!(split(":", ":a:"), ["", "a"]);
+        assert_eq!(split(":", "::"), ["", ""]);
+        assert_eq!(split(" :", " a : b "), ["a", "b"]);
+        assert_eq!(split(" :", "a : :b"), ["a", "", "b"]);
+        assert_eq!(split(" :", " :a"), ["", "a"]);
+    }
+
+    #[test]
+    fn expands_brace_sequences() {
+        assert_eq!(expand_braces("a{1..3}"), ["a1", "a2", "a3"]);
+        assert_eq!(expand_braces("{05..1..2}"), ["05", "03", "01"]);
+        assert_eq!(expand_braces("{c..a}"), ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn leaves_a_too_long_sequence_as_it_is() {
+        assert_eq!(expand_braces("{1..9999999999}"), ["{1..9999999999}"]);
+        assert_eq!(expand_braces("x{1..100001}"), ["x{1..100001}"]);
+        assert_eq!(expand_braces("{1..100000}").len(), 100000);
+        assert_eq!(expand_braces("{1..1000000..10}").len(), 100000);
+    }
+
+    #[test]
+    fn fails_on_an_unset_parameter() {
+        let unset = |word: &str| {
+            expand_word(word)
+                .unwrap_err()
+                .downcast::<UnsetParameter>()
+                .unwrap()
+                .to_string()
+        };
+
+        set_var("TSH_TEST_EMPTY", "");
+        set_var("TSH_TEST_SET", "a");
+        assert_eq!(
+            unset("${TSH_TEST_UNSET?is $TSH_TEST_SET}"),
+            "TSH_TEST_UNSET: is a"
+        );
+        assert_eq!(
+            unset("${TSH_TEST_EMPTY:?}"),
+            "TSH_TEST_EMPTY: parameter null or not set"
+        );
+        assert_eq!(expand_word("${TSH_TEST_EMPTY?}").unwrap(), "");
+        assert_eq!(expand_word("${TSH_TEST_SET:?}").unwrap(), "a");
+    }
+}
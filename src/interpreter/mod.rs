@@ -1,2 +1,6 @@
+mod arithmetic;
+pub mod environment;
 pub mod executor;
+mod expander;
 mod parser;
+mod pattern;
@@ -1,191 +1,1481 @@
-use std::{ops::Sub, path::PathBuf, vec};
+use std::{collections::VecDeque, fmt::Display, iter::Peekable, rc::Rc, sync::Arc, vec};
 
 use anyhow::{Result, anyhow};
 
-use crate::utils::report_line_err;
+use crate::interpreter::environment::{get_alias, is_valid_name, split_assignment};
 
 #[derive(Debug)]
 pub enum Command {
     Simple {
+        assignments: Vec<(String, String)>,
         command_name: String,
         args: Vec<String>,
         redirects: Vec<Redirect>,
         dont_wait: bool,
     },
+    Pipeline {
+        commands: Vec<Command>,
+        dont_wait: bool,
+    },
+    // left && right
+    And(Box<Command>, Box<Command>),
+    // left || right
+    Or(Box<Command>, Box<Command>),
+    // commands separated by ; or new lines
+    List(Vec<Command>),
+    // ((expression))
+    Arithmetic(String),
+    // if condition; then body; [elif condition; then body;]... [else otherwise;] fi
+    If {
+        branches: Vec<(Command, Command)>,
+        otherwise: Option<Box<Command>>,
+    },
+    // while condition; do body; done, or until
+    While {
+        condition: Box<Command>,
+        body: Box<Command>,
+        until: bool,
+    },
+    // for name in words; do body; done, without words it goes through the positional parameters
+    For {
+        name: String,
+        words: Option<Vec<String>>,
+        body: Box<Command>,
+    },
+    // case word in pattern | pattern) body;; ... esac
+    Case {
+        word: String,
+        items: Vec<(Vec<String>, Option<Command>)>,
+    },
+    // { body; }
+    Group(Box<Command>),
+    // name() body, running it defines the function
+    Function {
+        name: String,
+        body: Arc<Command>,
+    },
 }
 
-#[derive(Debug)]
+impl Command {
+    pub fn dont_wait(&self) -> bool {
+        match self {
+            Self::Simple { dont_wait, .. } | Self::Pipeline { dont_wait, .. } => *dont_wait,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Command {
+    // Writes the command back as it was typed, near enough to show it to the user
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Simple {
+                assignments,
+                command_name,
+                args,
+                redirects,
+                dont_wait,
+            } => {
+                let words = assignments
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .chain(
+                        [command_name.clone()]
+                            .into_iter()
+                            .filter(|name| !name.is_empty()),
+                    )
+                    .chain(args.iter().cloned())
+                    .chain(redirects.iter().map(Redirect::to_string))
+                    .collect::<Vec<_>>();
+
+                if *dont_wait {
+                    write!(f, "^")?;
+                }
+                write!(f, "{}", words.join(" "))
+            }
+            Self::Pipeline { commands, .. } => {
+                let commands = commands.iter().map(Command::to_string).collect::<Vec<_>>();
+                write!(f, "{}", commands.join(" | "))
+            }
+            Self::And(left, right) => write!(f, "{left} && {right}"),
+            Self::Or(left, right) => write!(f, "{left} || {right}"),
+            Self::List(commands) => {
+                let commands = commands.iter().map(Command::to_string).collect::<Vec<_>>();
+                write!(f, "{}", commands.join("; "))
+            }
+            Self::Arithmetic(expression) => write!(f, "(({expression}))"),
+            Self::If {
+                branches,
+                otherwise,
+            } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = if i == 0 { "if" } else { "elif" };
+                    write!(f, "{keyword} {condition}; then {body}; ")?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, "else {otherwise}; ")?;
+                }
+                write!(f, "fi")
+            }
+            Self::While {
+                condition,
+                body,
+                until,
+            } => {
+                let keyword = if *until { "until" } else { "while" };
+                write!(f, "{keyword} {condition}; do {body}; done")
+            }
+            Self::For { name, words, body } => {
+                write!(f, "for {name}")?;
+                if let Some(words) = words {
+                    write!(f, " in {}", words.join(" "))?;
+                }
+                write!(f, "; do {body}; done")
+            }
+            Self::Case { word, items } => {
+                write!(f, "case {word} in")?;
+                for (patterns, body) in items {
+                    write!(f, " {})", patterns.join(" | "))?;
+                    if let Some(body) = body {
+                        write!(f, " {body}")?;
+                    }
+                    write!(f, ";;")?;
+                }
+                write!(f, " esac")
+            }
+            Self::Group(body) => write!(f, "{{ {body}; }}"),
+            Self::Function { name, body } => write!(f, "{name}() {body}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum RedirectionType {
     Output,
     AppendOutput,
     RedirectToFileDescriptor(i32),
+    Input,
+    HereDocument { strip_tabs: bool },
+    HereString,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RedirectionTarget {
-    RealFile(PathBuf),
+    RealFile(String),
     FileDescriptor(i32),
+    HereDocument {
+        delimiter: String,
+        body: String,
+        expand: bool,
+    },
+    Text(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Redirect {
     pub from_fd: i32,
     pub kind: RedirectionType,
     pub target: RedirectionTarget,
 }
 
-pub fn try_parse_input(input: &str) -> Result<Option<Command>> {
-    let mut chars = input.trim().chars().peekable();
-    let mut single_quotes = false;
-    let mut double_quotes = false;
-    let mut arg_buffer = String::new();
-    let mut args = vec![];
-    let mut redirects = vec![];
-    let mut current_redirect: Option<(i32, RedirectionType)> = None;
-    let mut dont_wait = false;
+impl Display for Redirect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operator = match self.kind {
+            RedirectionType::Output => ">",
+            RedirectionType::AppendOutput => ">>",
+            RedirectionType::RedirectToFileDescriptor(_) => ">@",
+            RedirectionType::Input => "<",
+            RedirectionType::HereDocument { strip_tabs: false } => "<<",
+            RedirectionType::HereDocument { strip_tabs: true } => "<<-",
+            RedirectionType::HereString => "<<<",
+        };
 
-    while let Some(c) = chars.next() {
-        match c {
-            '\'' => {
-                if !double_quotes {
-                    single_quotes = !single_quotes;
+        match &self.target {
+            RedirectionTarget::RealFile(target) | RedirectionTarget::Text(target) => {
+                write!(f, "{}{operator}{target}", self.from_fd)
+            }
+            RedirectionTarget::FileDescriptor(fd) => write!(f, "{}{operator}{fd}", self.from_fd),
+            RedirectionTarget::HereDocument { delimiter, .. } => {
+                write!(f, "{}{operator}{delimiter}", self.from_fd)
+            }
+        }
+    }
+}
+
+/// The input ended in the middle of something that needs more lines to be complete, like an
+/// unterminated quote or a here-document without its delimiter. The caller should read more
+/// lines, append them to the input and try again.
+#[derive(Debug)]
+pub struct IncompleteInput;
+
+impl Display for IncompleteInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unexpected end of input")
+    }
+}
+
+impl std::error::Error for IncompleteInput {}
+
+/// The words that start a compound command or a part of it, when they are the first word of a
+/// command. `in` is only special right after the name of a `for` or the word of a `case`.
+pub const RESERVED_WORDS: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "then", "until",
+    "while", "{", "}",
+];
+
+/// The text being parsed, as chars. Cloning it to look ahead is cheap, and the word at the
+/// start of a command can be replaced by the value of its alias.
+#[derive(Clone)]
+struct Source {
+    chars: Rc<[char]>,
+    position: usize,
+}
+
+impl Source {
+    fn new(chars: impl Iterator<Item = char>) -> Input {
+        Self {
+            chars: chars.collect(),
+            position: 0,
+        }
+        .peekable()
+    }
+}
+
+impl Iterator for Source {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.position).copied()?;
+        self.position += 1;
+        Some(c)
+    }
+}
+
+type Input = Peekable<Source>;
+
+/// What the parser carries from one part of the input to another.
+#[derive(Default)]
+struct ParseState {
+    // The here-documents whose bodies are still to be read, with their delimiter and if the
+    // tabs at the start of the lines go away. The bodies start at the line after the one of
+    // their redirects and come in the same order, so they are read at every new line and
+    // given to the redirects once the whole input is parsed.
+    pending_here_documents: Vec<(String, bool)>,
+    here_document_bodies: VecDeque<String>,
+    // the aliases whose value is being parsed, with how many chars of the input come after it
+    aliases: Vec<(String, usize)>,
+    // where the value of an alias ending in a blank ended, the word after it is looked up too
+    blank_alias_end: Option<usize>,
+}
+
+impl ParseState {
+    /// Reads the bodies of the pending here-documents, the input must be at the start of a
+    /// line.
+    fn read_here_documents(&mut self, chars: &mut Input) -> Result<()> {
+        for (delimiter, strip_tabs) in self.pending_here_documents.drain(..) {
+            let mut body = String::new();
+            loop {
+                // Without the delimiter we need more lines
+                if chars.peek().is_none() {
+                    return Err(IncompleteInput.into());
+                }
+
+                let line = chars
+                    .by_ref()
+                    .take_while(|&c| c != '\n')
+                    .collect::<String>();
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
                 } else {
-                    arg_buffer.push(c);
+                    &line
+                };
+
+                if line == delimiter {
+                    break;
                 }
+                body.push_str(line);
+                body.push('\n');
             }
-            '"' => {
-                if !single_quotes {
-                    double_quotes = !double_quotes;
-                } else {
-                    arg_buffer.push(c);
+            self.here_document_bodies.push_back(body);
+        }
+
+        Ok(())
+    }
+}
+
+pub fn try_parse_input(input: &str) -> Result<Option<Command>> {
+    let mut chars = Source::new(input.trim().chars());
+    let mut state = ParseState::default();
+
+    let (mut command, _) = parse_list(&mut chars, &mut state, &[])?;
+    // the last line can have here-documents too
+    state.read_here_documents(&mut chars)?;
+
+    if let Some(command) = command.as_mut() {
+        fill_here_documents(command, &mut state.here_document_bodies);
+    }
+    Ok(command)
+}
+
+/// Parses commands separated by `;` or new lines, up to one of the terminators, a reserved
+/// word or `;;`, which is left unread and returned. Without terminators the list goes up to
+/// the end of the input, with them the end of the input means there are lines missing.
+fn parse_list(
+    chars: &mut Input,
+    state: &mut ParseState,
+    terminators: &[&'static str],
+) -> Result<(Option<Command>, Option<&'static str>)> {
+    let mut commands = vec![];
+    let mut separated = true;
+
+    let terminator = loop {
+        skip_blanks(chars);
+        // the end of an item of a case
+        let item_end = terminators.contains(&";;") && lookahead_is(chars, ";;");
+
+        match chars.peek() {
+            None if terminators.is_empty() => break None,
+            None => return Err(IncompleteInput.into()),
+            Some(';') if item_end => break Some(";;"),
+            Some('\n') => {
+                skip_linebreaks(chars, state)?;
+                separated = true;
+            }
+            Some(';') if !separated => {
+                chars.next();
+                separated = true;
+            }
+            Some(&c) if !separated || matches!(c, ';' | '&' | '|') => {
+                return Err(unexpected(chars));
+            }
+            Some(_) => {
+                if let Some(word) = peek_reserved_word(chars)
+                    && let Some(terminator) = terminators.iter().find(|&&t| t == word)
+                {
+                    break Some(*terminator);
                 }
+
+                commands.push(parse_and_or(chars, state)?);
+                separated = false;
             }
-            ' ' if !single_quotes && !double_quotes => {
-                if !arg_buffer.is_empty() {
-                    args.push(arg_buffer.clone());
-                    arg_buffer.clear();
+        }
+    };
+
+    let command = if commands.len() > 1 {
+        Some(Command::List(commands))
+    } else {
+        commands.pop()
+    };
+    Ok((command, terminator))
+}
+
+/// The list inside a compound command, up to one of the terminators, which is read too. Unlike
+/// the list of the whole input, it can't be empty.
+fn parse_compound_list(
+    chars: &mut Input,
+    state: &mut ParseState,
+    terminators: &[&'static str],
+) -> Result<(Command, &'static str)> {
+    let (command, terminator) = parse_list(chars, state, terminators)?;
+    let terminator = terminator.expect("A list with terminators ends at one of them");
+    skip_terminator(chars, terminator);
+
+    match command {
+        Some(command) => Ok((command, terminator)),
+        None => Err(anyhow!("Unexpected token '{terminator}'")),
+    }
+}
+
+fn parse_and_or(chars: &mut Input, state: &mut ParseState) -> Result<Command> {
+    let mut command = parse_pipeline(chars, state)?;
+
+    loop {
+        skip_blanks(chars);
+
+        let and = match chars.peek() {
+            Some('&') => {
+                chars.next();
+                if chars.next_if_eq(&'&').is_none() {
+                    return Err(anyhow!(
+                        "Unexpected token '&', to not wait for a command use: ^[command]"
+                    ));
                 }
+                true
             }
-            '>' if !single_quotes && !double_quotes => {
-                let mode = if chars.peek() == Some(&'>') {
-                    chars.next();
-                    RedirectionType::AppendOutput
-                } else if chars.peek() == Some(&'@') {
-                    // >@[fd_num]
+            // a single | was already taken by the pipeline
+            Some('|') => {
+                chars.next();
+                chars.next();
+                false
+            }
+            _ => break,
+        };
+
+        // the next command can be in the next line
+        skip_linebreaks(chars, state)?;
+        if chars.peek().is_none() {
+            return Err(IncompleteInput.into());
+        }
+
+        let right = Box::new(parse_pipeline(chars, state)?);
+        command = if and {
+            Command::And(Box::new(command), right)
+        } else {
+            Command::Or(Box::new(command), right)
+        };
+    }
+
+    Ok(command)
+}
+
+fn parse_pipeline(chars: &mut Input, state: &mut ParseState) -> Result<Command> {
+    let mut commands = vec![];
+
+    loop {
+        let command = parse_command(chars, state)?;
+        // a compound command ends at its last word, not at the operator after it
+        skip_blanks(chars);
+
+        // `||` is not a pipe, it's for the and-or list
+        let mut lookahead = chars.clone();
+        let piped = lookahead.next() == Some('|') && lookahead.next() != Some('|');
+
+        match command {
+            Some(command) => commands.push(command),
+            // `| cmd`, `cmd | | cmd` or `cmd && | cmd`, every stage of the pipeline needs a command
+            None => return Err(unexpected(chars)),
+        }
+
+        if !piped {
+            break;
+        }
+        chars.next();
+
+        // `cmd |` continues in the next line
+        skip_linebreaks(chars, state)?;
+        if chars.peek().is_none() {
+            return Err(IncompleteInput.into());
+        }
+    }
+
+    if commands.len() > 1 {
+        let dont_wait = commands.iter().any(Command::dont_wait);
+        Ok(Command::Pipeline {
+            commands,
+            dont_wait,
+        })
+    } else {
+        Ok(commands.pop().expect("A pipeline has at least one command"))
+    }
+}
+
+fn parse_command(chars: &mut Input, state: &mut ParseState) -> Result<Option<Command>> {
+    skip_blanks(chars);
+    expand_alias(chars, state);
+    if lookahead_is(chars, "((") {
+        return read_arithmetic_command(chars).map(Some);
+    }
+
+    match peek_reserved_word(chars) {
+        Some("if") => parse_if(chars, state).map(Some),
+        Some("while") => parse_while(chars, state, false).map(Some),
+        Some("until") => parse_while(chars, state, true).map(Some),
+        Some("for") => parse_for(chars, state).map(Some),
+        Some("case") => parse_case(chars, state).map(Some),
+        Some("{") => parse_group(chars, state).map(Some),
+        Some("function") => parse_function(chars, state).map(Some),
+        // a `then` or `done` out of its place
+        Some(word) => Err(anyhow!("Unexpected token '{word}'")),
+        None if is_function_definition(chars) => parse_function(chars, state).map(Some),
+        None => try_parse_simple_command(chars, state),
+    }
+}
+
+/// Replaces the word that comes next with the value of its alias, if it has one, then the
+/// first word of that value the same way. An alias is not looked up again within its own
+/// value, so `alias ls='ls -F'` works and `alias a=b b=a` ends. Returns if there was any.
+fn expand_alias(chars: &mut Input, state: &mut ParseState) -> bool {
+    let mut expanded = false;
+
+    loop {
+        skip_blanks(chars);
+        let mut lookahead = chars.clone();
+        // quoted or escaped, the word is not the name of the alias anymore
+        let word = read_word(&mut lookahead).unwrap_or_default();
+        let Some(value) = get_alias(&word) else {
+            return expanded;
+        };
+
+        // the values that ended before this word are over
+        let remaining = chars.clone().count();
+        state.aliases.retain(|(_, rest)| *rest < remaining);
+        if state.aliases.iter().any(|(name, _)| *name == word) {
+            return expanded;
+        }
+
+        let rest = lookahead.collect::<Vec<_>>();
+        if value.ends_with([' ', '\t']) {
+            state.blank_alias_end = Some(rest.len());
+        }
+        state.aliases.push((word, rest.len()));
+        *chars = Source::new(value.chars().chain(rest));
+        expanded = true;
+    }
+}
+
+/// `{ list; }`, the list runs in the shell itself.
+fn parse_group(chars: &mut Input, state: &mut ParseState) -> Result<Command> {
+    expect_reserved_word(chars, "{")?;
+    let (body, _) = parse_compound_list(chars, state, &["}"])?;
+
+    Ok(Command::Group(Box::new(body)))
+}
+
+/// If the input starts with `name()`.
+fn is_function_definition(chars: &Input) -> bool {
+    let mut lookahead = chars.clone();
+    let name = read_word(&mut lookahead).unwrap_or_default();
+    skip_blanks(&mut lookahead);
+    if !is_function_name(&name) || lookahead.next() != Some('(') {
+        return false;
+    }
+
+    skip_blanks(&mut lookahead);
+    lookahead.next() == Some(')')
+}
+
+/// Letters, digits, `_`, `-` and `.`, so `git-log` or `my.cmd` can be functions too.
+fn is_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.chars().all(|c| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// `name() compound-command` or `function name [()] compound-command`, where the body is
+/// usually a `{ list; }` and can start in the next line.
+fn parse_function(chars: &mut Input, state: &mut ParseState) -> Result<Command> {
+    if peek_reserved_word(chars) == Some("function") {
+        expect_reserved_word(chars, "function")?;
+        skip_blanks(chars);
+    }
+
+    let name = read_word(chars)?;
+    if name.is_empty() {
+        return Err(unexpected(chars));
+    } else if !is_function_name(&name) {
+        return Err(anyhow!("'{name}': not a valid function name"));
+    }
+
+    skip_blanks(chars);
+    if chars.next_if_eq(&'(').is_some() {
+        skip_blanks(chars);
+        if chars.next_if_eq(&')').is_none() {
+            return Err(unexpected(chars));
+        }
+    }
+
+    skip_linebreaks(chars, state)?;
+    if chars.peek().is_none() {
+        return Err(IncompleteInput.into());
+    }
+    match parse_command(chars, state)? {
+        Some(Command::Simple { .. }) | None => Err(anyhow!(
+            "The body of the function {name} must be a compound command, as {{ list; }}"
+        )),
+        Some(body) => Ok(Command::Function {
+            name,
+            body: Arc::new(body),
+        }),
+    }
+}
+
+/// `if list; then list; [elif list; then list;]... [else list;] fi`
+fn parse_if(chars: &mut Input, state: &mut ParseState) -> Result<Command> {
+    expect_reserved_word(chars, "if")?;
+
+    let mut branches = vec![];
+    loop {
+        let (condition, _) = parse_compound_list(chars, state, &["then"])?;
+        let (body, terminator) = parse_compound_list(chars, state, &["elif", "else", "fi"])?;
+        branches.push((condition, body));
+
+        match terminator {
+            "elif" => {}
+            "else" => {
+                let (otherwise, _) = parse_compound_list(chars, state, &["fi"])?;
+                return Ok(Command::If {
+                    branches,
+                    otherwise: Some(Box::new(otherwise)),
+                });
+            }
+            _ => {
+                return Ok(Command::If {
+                    branches,
+                    otherwise: None,
+                });
+            }
+        }
+    }
+}
+
+/// `while list; do list; done` or `until list; do list; done`
+fn parse_while(chars: &mut Input, state: &mut ParseState, until: bool) -> Result<Command> {
+    expect_reserved_word(chars, if until { "until" } else { "while" })?;
+
+    let (condition, _) = parse_compound_list(chars, state, &["do"])?;
+    let (body, _) = parse_compound_list(chars, state, &["done"])?;
+
+    Ok(Command::While {
+        condition: Box::new(condition),
+        body: Box::new(body),
+        until,
+    })
+}
+
+/// `for name [in word...]; do list; done`, the `;` can be a new line too
+fn parse_for(chars: &mut Input, state: &mut ParseState) -> Result<Command> {
+    expect_reserved_word(chars, "for")?;
+
+    skip_blanks(chars);
+    let name = read_word(chars)?;
+    if name.is_empty() {
+        return Err(unexpected(chars));
+    } else if !is_valid_name(&name) {
+        return Err(anyhow!("'{name}': not a valid identifier"));
+    }
+
+    skip_linebreaks(chars, state)?;
+    let words = if peek_word(chars) == "in" {
+        expect_reserved_word(chars, "in")?;
+
+        let mut words = vec![];
+        loop {
+            skip_blanks(chars);
+            match chars.peek() {
+                None => return Err(IncompleteInput.into()),
+                Some('\n') => break,
+                Some(';') => {
                     chars.next();
-                    let mut fd_num = String::with_capacity(1);
-                    for fd_part in chars.by_ref() {
-                        if !fd_part.is_ascii_digit() {
-                            break;
-                        }
-                        fd_num.push(fd_part);
-                    }
+                    break;
+                }
+                Some(&c) if "|&<>()".contains(c) => return Err(anyhow!("Unexpected token '{c}'")),
+                Some(_) => words.push(read_word(chars)?),
+            }
+        }
+        Some(words)
+    } else {
+        // without words, the loop goes through the positional parameters
+        chars.next_if_eq(&';');
+        None
+    };
 
-                    if fd_num.is_empty() {
-                        return Err(anyhow!(
-                            "Invalid number for redirect to file descriptor: [fd_to_redirect?1]>@[fd_to_receive_redirect]"
-                        ));
-                    }
+    skip_linebreaks(chars, state)?;
+    expect_reserved_word(chars, "do")?;
+    let (body, _) = parse_compound_list(chars, state, &["done"])?;
+
+    Ok(Command::For {
+        name,
+        words,
+        body: Box::new(body),
+    })
+}
 
-                    if let Ok(fd_num) = fd_num.parse() {
-                        RedirectionType::RedirectToFileDescriptor(fd_num)
-                    } else {
-                        report_line_err(Some(format!("Failed to parse 'redirect to' number file descriptor: Tried parse {}", fd_num).as_str()));
-                        panic!()
+/// `case word in [(]pattern [| pattern]...) list;; ... esac`, the `;;` of the last item can be
+/// left out and its list can be empty.
+fn parse_case(chars: &mut Input, state: &mut ParseState) -> Result<Command> {
+    expect_reserved_word(chars, "case")?;
+
+    skip_blanks(chars);
+    let word = read_word(chars)?;
+    if word.is_empty() {
+        return Err(unexpected(chars));
+    }
+
+    skip_linebreaks(chars, state)?;
+    expect_reserved_word(chars, "in")?;
+
+    let mut items = vec![];
+    loop {
+        skip_linebreaks(chars, state)?;
+        if peek_reserved_word(chars) == Some("esac") {
+            expect_reserved_word(chars, "esac")?;
+            break;
+        }
+
+        chars.next_if_eq(&'(');
+        let mut patterns = vec![];
+        loop {
+            skip_blanks(chars);
+            let pattern = read_word(chars)?;
+            if pattern.is_empty() {
+                return Err(unexpected(chars));
+            }
+            patterns.push(pattern);
+
+            skip_blanks(chars);
+            match chars.next() {
+                Some('|') => {}
+                Some(')') => break,
+                Some(c) => return Err(anyhow!("Unexpected token '{c}', expected ')'")),
+                None => return Err(IncompleteInput.into()),
+            }
+        }
+
+        let (body, terminator) = parse_list(chars, state, &[";;", "esac"])?;
+        let terminator = terminator.expect("A list with terminators ends at one of them");
+        skip_terminator(chars, terminator);
+        items.push((patterns, body));
+
+        if terminator == "esac" {
+            break;
+        }
+    }
+
+    Ok(Command::Case { word, items })
+}
+
+/// Gives the here-documents of the command their bodies, in the order they were read.
+fn fill_here_documents(command: &mut Command, bodies: &mut VecDeque<String>) {
+    match command {
+        Command::Simple { redirects, .. } => {
+            for redirect in redirects.iter_mut() {
+                if let RedirectionTarget::HereDocument { body, .. } = &mut redirect.target {
+                    *body = bodies.pop_front().unwrap_or_default();
+                }
+            }
+        }
+        Command::Pipeline { commands, .. } | Command::List(commands) => {
+            for command in commands.iter_mut() {
+                fill_here_documents(command, bodies);
+            }
+        }
+        Command::And(left, right) | Command::Or(left, right) => {
+            fill_here_documents(left, bodies);
+            fill_here_documents(right, bodies);
+        }
+        Command::If {
+            branches,
+            otherwise,
+        } => {
+            for (condition, body) in branches.iter_mut() {
+                fill_here_documents(condition, bodies);
+                fill_here_documents(body, bodies);
+            }
+            if let Some(otherwise) = otherwise {
+                fill_here_documents(otherwise, bodies);
+            }
+        }
+        Command::While {
+            condition, body, ..
+        } => {
+            fill_here_documents(condition, bodies);
+            fill_here_documents(body, bodies);
+        }
+        Command::For { body, .. } => fill_here_documents(body, bodies),
+        Command::Case { items, .. } => {
+            for body in items.iter_mut().filter_map(|(_, body)| body.as_mut()) {
+                fill_here_documents(body, bodies);
+            }
+        }
+        Command::Group(body) => fill_here_documents(body, bodies),
+        Command::Function { body, .. } => {
+            // nobody else has the body while parsing
+            if let Some(body) = Arc::get_mut(body) {
+                fill_here_documents(body, bodies);
+            }
+        }
+        Command::Arithmetic(_) => {}
+    }
+}
+
+/// Skips the blanks and the comment after them, if any, up to the end of the line.
+fn skip_blanks(chars: &mut Input) {
+    while chars.next_if(|&c| c == ' ' || c == '\t').is_some() {}
+
+    if chars.peek() == Some(&'#') {
+        while chars.next_if(|&c| c != '\n').is_some() {}
+    }
+}
+
+/// Skips the blanks, comments and new lines, reading the here-documents that start at each
+/// of those lines.
+fn skip_linebreaks(chars: &mut Input, state: &mut ParseState) -> Result<()> {
+    loop {
+        skip_blanks(chars);
+        if chars.next_if_eq(&'\n').is_none() {
+            return Ok(());
+        }
+        state.read_here_documents(chars)?;
+    }
+}
+
+fn lookahead_is(chars: &Input, text: &str) -> bool {
+    chars.clone().take(text.chars().count()).eq(text.chars())
+}
+
+/// The next word without reading it, quotes included.
+fn peek_word(chars: &Input) -> String {
+    read_word(&mut chars.clone()).unwrap_or_default()
+}
+
+/// The next word if it is a reserved one. Only as a whole and unquoted, `"done"` or `done2`
+/// are not reserved words.
+fn peek_reserved_word(chars: &Input) -> Option<&'static str> {
+    let word = peek_word(chars);
+    RESERVED_WORDS
+        .iter()
+        .find(|&&reserved| reserved == word)
+        .copied()
+}
+
+/// Reads the reserved word that must come next.
+fn expect_reserved_word(chars: &mut Input, expected: &str) -> Result<()> {
+    skip_blanks(chars);
+    match peek_word(chars) {
+        word if word == expected => {
+            skip_terminator(chars, expected);
+            Ok(())
+        }
+        word if word.is_empty() => Err(match chars.peek() {
+            Some(c) => anyhow!("Unexpected token '{c}', expected '{expected}'"),
+            None => IncompleteInput.into(),
+        }),
+        word => Err(anyhow!("Unexpected token '{word}', expected '{expected}'")),
+    }
+}
+
+/// Reads a terminator already known to be next.
+fn skip_terminator(chars: &mut Input, terminator: &str) {
+    for _ in terminator.chars() {
+        chars.next();
+    }
+}
+
+/// The error for what comes next, when it's not what was expected.
+fn unexpected(chars: &mut Input) -> anyhow::Error {
+    match (peek_word(chars), chars.peek()) {
+        (word, _) if !word.is_empty() => anyhow!("Unexpected token '{word}'"),
+        (_, Some(c)) => anyhow!("Unexpected token '{c}'"),
+        (_, None) => IncompleteInput.into(),
+    }
+}
+
+fn try_parse_simple_command(chars: &mut Input, state: &mut ParseState) -> Result<Option<Command>> {
+    let mut args: Vec<String> = vec![];
+    let mut redirects = vec![];
+    let mut dont_wait = false;
+
+    loop {
+        skip_blanks(chars);
+
+        match chars.peek() {
+            // operators and the end of line end this command, leave them to the list parsing
+            None | Some('|') | Some('\n') | Some(';') | Some('&') | Some('(') | Some(')') => break,
+            Some('>') | Some('<') => redirects.push(read_redirect(None, chars, state)?),
+            Some(_) => {
+                // After the assignments the word is still the name of the command, and after
+                // an alias ending in a blank the word can be an alias too
+                let after_blank_alias = state
+                    .blank_alias_end
+                    .is_some_and(|end| chars.clone().count() <= end);
+                if after_blank_alias {
+                    state.blank_alias_end = None;
+                }
+                if !args.is_empty()
+                    && (after_blank_alias || args.iter().all(|arg| split_assignment(arg).is_some()))
+                    && expand_alias(chars, state)
+                {
+                    continue;
+                }
+
+                let word = read_word(chars)?;
+
+                // [fd]>[file] or [fd]<[file], the fd must be glued to the redirect
+                if matches!(chars.peek(), Some('>') | Some('<'))
+                    && let Ok(fd) = word.parse::<i32>()
+                {
+                    redirects.push(read_redirect(Some(fd), chars, state)?);
+                    continue;
+                }
+
+                match word.strip_prefix('^') {
+                    Some(word) if args.is_empty() && !word.is_empty() => {
+                        dont_wait = true;
+                        args.push(word.to_owned());
                     }
-                } else if chars.peek().is_none() {
+                    _ => args.push(word),
+                }
+            }
+        }
+    }
+
+    // the word after the alias ending in a blank was not in this command
+    if state
+        .blank_alias_end
+        .is_some_and(|end| chars.clone().count() <= end)
+    {
+        state.blank_alias_end = None;
+    }
+
+    // `NAME=value` words before the command are assignments just for it
+    let mut assignments = vec![];
+    while let Some(assignment) = args
+        .first()
+        .and_then(|arg| split_assignment(arg))
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+    {
+        assignments.push(assignment);
+        args.remove(0);
+    }
+
+    if args.is_empty() && assignments.is_empty() {
+        return Ok(None);
+    }
+
+    // Without command name, the assignments are for the shell itself
+    let name = args.first().cloned().unwrap_or_default();
+
+    Ok(Some(Command::Simple {
+        assignments,
+        command_name: name,
+        args: args.into_iter().skip(1).collect(),
+        redirects,
+        dont_wait,
+    }))
+}
+
+/// Reads `((expression))`, the expression keeps its quotes for the expansions.
+fn read_arithmetic_command(chars: &mut Input) -> Result<Command> {
+    chars.next();
+    chars.next();
+
+    // The same as the expression of a `$((`, up to the `))` that closes it
+    let expression = read_substitution(chars)?;
+    match chars.next() {
+        Some(')') => Ok(Command::Arithmetic(expression)),
+        Some(c) => Err(anyhow!("Unexpected token '{c}', expected '))'")),
+        None => Err(IncompleteInput.into()),
+    }
+}
+
+fn read_redirect(
+    from_fd: Option<i32>,
+    chars: &mut Input,
+    state: &mut ParseState,
+) -> Result<Redirect> {
+    let mode = match chars.next() {
+        Some('>') => {
+            if chars.next_if_eq(&'>').is_some() {
+                RedirectionType::AppendOutput
+            } else if chars.next_if_eq(&'@').is_some() {
+                // >@[fd_num]
+                let mut fd_num = String::with_capacity(1);
+                while let Some(fd_part) = chars.next_if(char::is_ascii_digit) {
+                    fd_num.push(fd_part);
+                }
+
+                if fd_num.is_empty() {
                     return Err(anyhow!(
-                        "Unexpected redirect token after '>': [fd_to_redirect?1]>[file]"
+                        "Invalid number for redirect to file descriptor: [fd_to_redirect?1]>@[fd_to_receive_redirect]"
                     ));
-                } else {
-                    RedirectionType::Output
-                };
+                }
 
-                if let Ok(num) = arg_buffer.parse::<i32>() {
-                    match mode {
-                        RedirectionType::RedirectToFileDescriptor(fd) => {
-                            redirects.push(Redirect {
-                                from_fd: num,
-                                kind: mode,
-                                target: RedirectionTarget::FileDescriptor(fd),
-                            });
-                        }
-                        _ => {
-                            current_redirect = Some((num, mode));
-                        }
+                let fd_num = fd_num.parse().map_err(|_| {
+                    anyhow!(
+                        "Failed to parse 'redirect to' number file descriptor: Tried parse {fd_num}"
+                    )
+                })?;
+                RedirectionType::RedirectToFileDescriptor(fd_num)
+            } else {
+                RedirectionType::Output
+            }
+        }
+        _ => {
+            if chars.next_if_eq(&'<').is_none() {
+                RedirectionType::Input
+            } else if chars.next_if_eq(&'<').is_some() {
+                // <<<[word]
+                RedirectionType::HereString
+            } else {
+                // <<[delimiter] or <<-[delimiter]
+                RedirectionType::HereDocument {
+                    strip_tabs: chars.next_if_eq(&'-').is_some(),
+                }
+            }
+        }
+    };
+
+    if let RedirectionType::RedirectToFileDescriptor(fd) = mode {
+        return Ok(Redirect {
+            from_fd: from_fd.unwrap_or(1),
+            kind: mode,
+            target: RedirectionTarget::FileDescriptor(fd),
+        });
+    }
+
+    skip_blanks(chars);
+    if matches!(
+        chars.peek(),
+        None | Some('|')
+            | Some('\n')
+            | Some(';')
+            | Some('&')
+            | Some('<')
+            | Some('>')
+            | Some('(')
+            | Some(')')
+    ) {
+        return Err(match mode {
+            RedirectionType::Output | RedirectionType::AppendOutput => {
+                anyhow!("Unexpected redirect token after '>': [fd_to_redirect?1]>[file]")
+            }
+            _ => anyhow!("Unexpected redirect token after '<': [fd_to_redirect?0]<[file]"),
+        });
+    }
+
+    let word = read_word(chars)?;
+    let (from_fd, target) = match mode {
+        RedirectionType::HereDocument { strip_tabs } => {
+            let delimiter = word.replace(['\'', '"', '\\'], "");
+            // the body comes in the next lines
+            state
+                .pending_here_documents
+                .push((delimiter.clone(), strip_tabs));
+            (
+                from_fd.unwrap_or(0),
+                RedirectionTarget::HereDocument {
+                    // Quoting any part of the delimiter disables the expansions in the body
+                    expand: !word.contains(['\'', '"', '\\']),
+                    delimiter,
+                    body: String::new(),
+                },
+            )
+        }
+        RedirectionType::HereString => (from_fd.unwrap_or(0), RedirectionTarget::Text(word)),
+        RedirectionType::Input => (from_fd.unwrap_or(0), RedirectionTarget::RealFile(word)),
+        _ => (from_fd.unwrap_or(1), RedirectionTarget::RealFile(word)),
+    };
+
+    Ok(Redirect {
+        from_fd,
+        kind: mode,
+        target,
+    })
+}
+
+/// Reads a word as it was typed, quotes and backslashes included, so the expansions can
+/// later know what was quoted. It stops at the first unquoted blank or operator.
+fn read_word(chars: &mut Input) -> Result<String> {
+    let mut single_quotes = false;
+    let mut double_quotes = false;
+    let mut braces = 0usize;
+    let mut word = String::new();
+
+    while let Some(&c) = chars.peek() {
+        if single_quotes {
+            single_quotes = c != '\'';
+        } else {
+            match c {
+                ' ' | '\t' | '\n' | '|' | ';' | '&' | '<' | '>' | '(' | ')'
+                    if !double_quotes && braces == 0 =>
+                {
+                    break;
+                }
+                '\\' => {
+                    word.push(c);
+                    chars.next();
+                    // the escaped char is kept as it is, whatever it is
+                    match chars.next() {
+                        Some(escaped) => word.push(escaped),
+                        None => return Err(IncompleteInput.into()),
                     }
-                    arg_buffer.clear();
-                } else {
-                    current_redirect = Some((1, mode));
+                    continue;
+                }
+                '\'' if !double_quotes => single_quotes = true,
+                '"' => double_quotes = !double_quotes,
+                '$' => {
+                    word.push(c);
+                    chars.next();
+                    if chars.next_if_eq(&'{').is_some() {
+                        word.push('{');
+                        braces += 1;
+                    } else if chars.next_if_eq(&'(').is_some() {
+                        word.push('(');
+                        word.push_str(&read_substitution(chars)?);
+                        word.push(')');
+                    }
+                    continue;
+                }
+                '`' => {
+                    word.push(c);
+                    chars.next();
+                    word.push_str(&read_backquoted(chars)?);
+                    word.push('`');
+                    continue;
                 }
+                '}' if braces > 0 => braces -= 1,
+                _ => {}
+            }
+        }
+
+        word.push(c);
+        chars.next();
+    }
+
+    if single_quotes || double_quotes || braces > 0 {
+        return Err(IncompleteInput.into());
+    }
+
+    Ok(word)
+}
+
+/// Where a `case` inside a command substitution is, to know which `)` only ends a pattern.
+#[derive(Clone, Copy, PartialEq)]
+enum CaseState {
+    // the word after `case`
+    Word,
+    // the `in` after the word
+    In,
+    // a pattern, up to the `)` that ends it
+    Pattern,
+    // the commands of a pattern, up to `;;` or `esac`
+    Body,
+}
+
+/// Reads the command of a `$(` up to the `)` that closes it, the `$(` must be already read
+/// and the `)` is left out. The quotes inside are its own, even within double quotes. The
+/// `)` that ends a pattern of a `case` closes nothing, so the `case` and its words are
+/// followed.
+pub fn read_substitution<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Result<String> {
+    let mut single_quotes = false;
+    let mut double_quotes = false;
+    let mut depth = 1usize;
+    let mut command = String::new();
+    // the unquoted word being read, and if it's at the start of a command
+    let mut word = String::new();
+    let mut command_start = true;
+    let mut cases = vec![];
+
+    loop {
+        let Some(c) = chars.next() else {
+            return Err(IncompleteInput.into());
+        };
+
+        if single_quotes {
+            single_quotes = c != '\'';
+            command.push(c);
+            continue;
+        }
+
+        let delimiter = !double_quotes && (c.is_whitespace() || "();&|<>".contains(c));
+        if delimiter {
+            end_substitution_word(&mut word, &mut command_start, &mut cases);
+        } else {
+            // with quotes or escapes in it, it's no reserved word
+            word.push(c);
+        }
+
+        match c {
+            '\\' => {
+                command.push(c);
+                match chars.next() {
+                    Some(escaped) => command.push(escaped),
+                    None => return Err(IncompleteInput.into()),
+                }
+                continue;
+            }
+            '\'' if !double_quotes => single_quotes = true,
+            '"' => double_quotes = !double_quotes,
+            '$' if chars.peek() == Some(&'(') => {
+                chars.next();
+                command.push_str("$(");
+                command.push_str(&read_substitution(chars)?);
+                command.push(')');
+                continue;
+            }
+            '`' => {
+                command.push(c);
+                command.push_str(&read_backquoted(chars)?);
+                command.push(c);
+                continue;
             }
-            '^' if !single_quotes
-                && !double_quotes
-                && chars.peek().ne(&Some(&' '))
-                && arg_buffer.is_empty() =>
+            // a pattern can start with a `(` of its own
+            '(' if !double_quotes && cases.last() == Some(&CaseState::Pattern) => {}
+            ')' if !double_quotes && cases.last() == Some(&CaseState::Pattern) => {
+                cases.pop();
+                cases.push(CaseState::Body);
+            }
+            '(' if !double_quotes => depth += 1,
+            ')' if !double_quotes => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(command);
+                }
+            }
+            // `;;`, `;&` and `;;&` go on with the next pattern
+            ';' if cases.last() == Some(&CaseState::Body)
+                && matches!(chars.peek(), Some(';') | Some('&')) =>
             {
-                dont_wait = true
+                cases.pop();
+                cases.push(CaseState::Pattern);
+                command.push(c);
+                command.extend(chars.next());
+                if command.ends_with(';') {
+                    command.extend(chars.next_if_eq(&'&'));
+                }
+                continue;
             }
-            _ => {
-                if let Some((fd, mode)) = current_redirect {
-                    match mode {
-                        RedirectionType::Output | RedirectionType::AppendOutput => {
-                            let mut single_quotes = false;
-                            let mut double_quotes = false;
-                            let mut path = String::from(c);
-                            for arg_redirect_c in chars.by_ref() {
-                                match arg_redirect_c {
-                                    ' ' if !single_quotes && !double_quotes => {
-                                        break;
-                                    }
-                                    '\'' => {
-                                        if !double_quotes {
-                                            single_quotes = !single_quotes;
-                                        }
-                                    }
-                                    '"' => {
-                                        if !single_quotes {
-                                            double_quotes = !double_quotes;
-                                        }
-                                    }
-                                    _ => path.push(arg_redirect_c),
-                                }
-                            }
-                            redirects.push(Redirect {
-                                from_fd: fd,
-                                kind: mode,
-                                target: RedirectionTarget::RealFile(PathBuf::from(path)),
-                            });
-                        }
-                        _ => {
-                            // do not rely on having a next character to process the file redirect,
-                            // that's why we process it as soon as we detect a redirect.
-                        }
-                    }
+            _ => {}
+        }
+        if !double_quotes && ";&|()\n".contains(c) {
+            command_start = true;
+        }
+        command.push(c);
+    }
+}
 
-                    current_redirect = None;
-                } else {
-                    arg_buffer.push(c);
+/// Follows the `case` commands of a command substitution with the word that just ended.
+fn end_substitution_word(word: &mut String, command_start: &mut bool, cases: &mut Vec<CaseState>) {
+    if word.is_empty() {
+        return;
+    }
+    let word = std::mem::take(word);
+
+    match cases.last().copied() {
+        Some(CaseState::Word) => {
+            cases.pop();
+            cases.push(CaseState::In);
+        }
+        Some(CaseState::In) if word == "in" => {
+            cases.pop();
+            cases.push(CaseState::Pattern);
+        }
+        Some(CaseState::Pattern) if word == "esac" => {
+            cases.pop();
+        }
+        // the words of a pattern
+        Some(CaseState::In | CaseState::Pattern) => {}
+        _ if *command_start && word == "case" => cases.push(CaseState::Word),
+        Some(CaseState::Body) if *command_start && word == "esac" => {
+            cases.pop();
+        }
+        _ => {}
+    }
+
+    // after these another command starts right away
+    *command_start = matches!(
+        word.as_str(),
+        "if" | "then" | "else" | "elif" | "do" | "while" | "until" | "!" | "{"
+    );
+}
+
+/// Reads up to the closing backquote, the opening one must be already read and the closing
+/// one is left out. The backslashes are kept, they are for the expansion to handle.
+pub fn read_backquoted<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> Result<String> {
+    let mut command = String::new();
+
+    loop {
+        match chars.next() {
+            Some('`') => return Ok(command),
+            Some('\\') => {
+                command.push('\\');
+                match chars.next() {
+                    Some(escaped) => command.push(escaped),
+                    None => return Err(IncompleteInput.into()),
                 }
             }
+            Some(c) => command.push(c),
+            None => return Err(IncompleteInput.into()),
         }
     }
+}
 
-    if !arg_buffer.is_empty() {
-        args.push(arg_buffer);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::environment::set_alias;
+
+    /// The command parsed, written back.
+    fn parse(input: &str) -> String {
+        try_parse_input(input).unwrap().unwrap().to_string()
     }
 
-    if let Some(name) = args.first() {
-        let name = name.trim().to_string();
-        let take = args.len().sub(if dont_wait { 1 } else { 0 });
+    fn is_incomplete(input: &str) -> bool {
+        try_parse_input(input)
+            .unwrap_err()
+            .downcast_ref::<IncompleteInput>()
+            .is_some()
+    }
 
-        Ok(Some(Command::Simple {
-            command_name: name,
-            args: args.into_iter().skip(1).take(take).collect(),
-            redirects,
-            dont_wait,
-        }))
-    } else {
-        Ok(None)
+    fn here_documents(input: &str) -> Vec<(String, bool)> {
+        let Some(Command::Simple { redirects, .. }) = try_parse_input(input).unwrap() else {
+            panic!("{input} is not a simple command");
+        };
+        redirects
+            .into_iter()
+            .filter_map(|redirect| match redirect.target {
+                RedirectionTarget::HereDocument { body, expand, .. } => Some((body, expand)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_the_compound_commands() {
+        assert_eq!(
+            parse("if a; then b; elif c; then d; else e; fi"),
+            "if a; then b; elif c; then d; else e; fi"
+        );
+        assert_eq!(parse("while a; do b; done"), "while a; do b; done");
+        assert_eq!(parse("until a\ndo\n b\ndone"), "until a; do b; done");
+        assert_eq!(
+            parse("for i in 1 2; do echo $i; done"),
+            "for i in 1 2; do echo $i; done"
+        );
+        assert_eq!(parse("for i; do echo; done"), "for i; do echo; done");
+        assert_eq!(
+            parse("case $x in a|b) echo ab;; *) ;; esac"),
+            "case $x in a | b) echo ab;; *);; esac"
+        );
+        assert_eq!(
+            parse("case x in (a) echo;; esac"),
+            "case x in a) echo;; esac"
+        );
+        assert_eq!(parse("{ a; b; }"), "{ a; b; }");
+        assert_eq!(parse("f() { echo $1; }"), "f() { echo $1; }");
+        assert_eq!(parse("function f { echo; }"), "f() { echo; }");
+        assert_eq!(parse("a && b || c; d | e"), "a && b || c; d | e");
+        assert_eq!(parse("^sleep 1"), "^sleep 1");
+        assert_eq!(parse("(( 1 + 2 ))"), "(( 1 + 2 ))");
+    }
+
+    #[test]
+    fn rejects_what_is_out_of_place() {
+        for input in [
+            "fi",
+            "done",
+            "if true; fi",
+            "echo )",
+            "f() echo",
+            "echo a >@",
+            "echo a >@99999999999",
+        ] {
+            assert!(try_parse_input(input).is_err(), "{input}");
+            assert!(!is_incomplete(input), "{input}");
+        }
+    }
+
+    #[test]
+    fn asks_for_more_lines_when_unfinished() {
+        for input in [
+            "if true; then",
+            "while true; do echo",
+            "case x in",
+            "{ echo",
+            "echo 'a",
+            "echo \"a",
+            "echo $(echo",
+            "echo a |",
+            "a &&",
+            "cat <<EOF\nbody",
+        ] {
+            assert!(is_incomplete(input), "{input}");
+        }
+    }
+
+    #[test]
+    fn reads_the_here_documents() {
+        assert_eq!(
+            here_documents("cat <<EOF\nhello $x\nEOF"),
+            [("hello $x\n".to_owned(), true)]
+        );
+        assert_eq!(
+            here_documents("cat <<'EOF'\n$x\nEOF"),
+            [("$x\n".to_owned(), false)]
+        );
+        assert_eq!(
+            here_documents("cat <<-EOF\n\tindented\n\tEOF"),
+            [("indented\n".to_owned(), true)]
+        );
+        assert_eq!(
+            here_documents("cat <<A <<B\na\nA\nb\nB"),
+            [("a\n".to_owned(), true), ("b\n".to_owned(), true)]
+        );
+    }
+
+    #[test]
+    fn finds_the_end_of_a_command_substitution() {
+        let read = |text: &str| {
+            let mut chars = text.chars().peekable();
+            let command = read_substitution(&mut chars).unwrap();
+            (command, chars.collect::<String>())
+        };
+
+        assert_eq!(read("echo a) b"), ("echo a".into(), " b".into()));
+        assert_eq!(
+            read("echo $(echo a)) b"),
+            ("echo $(echo a)".into(), " b".into())
+        );
+        assert_eq!(
+            read("echo ')' \")\") b"),
+            ("echo ')' \")\"".into(), " b".into())
+        );
+        assert_eq!(
+            read("case x in a) echo y;; esac) z"),
+            ("case x in a) echo y;; esac".into(), " z".into())
+        );
+        assert_eq!(
+            read("case x in (a|b) echo;& *) case y in y) ;; esac;; esac)"),
+            (
+                "case x in (a|b) echo;& *) case y in y) ;; esac;; esac".into(),
+                "".into()
+            )
+        );
+        // only a case at the start of a command counts
+        assert_eq!(
+            read("echo case x in a) b"),
+            ("echo case x in a".into(), " b".into())
+        );
+        assert_eq!(
+            parse("echo $(case x in a) echo y;; esac) z"),
+            "echo $(case x in a) echo y;; esac) z"
+        );
+        assert!(read_substitution(&mut "case x in a) echo".chars().peekable()).is_err());
+    }
+
+    #[test]
+    fn expands_the_aliases() {
+        // the aliases are shared by the tests running at the same time, so the names are not
+        set_alias("tsh_test_ll", "tsh_test_ls -l");
+        set_alias("tsh_test_ls", "tsh_test_ls -F");
+        set_alias("tsh_test_a", "tsh_test_b");
+        set_alias("tsh_test_b", "tsh_test_a");
+        set_alias("tsh_test_sudo", "sudo ");
+        set_alias("tsh_test_nice", "nice");
+        set_alias("tsh_test_echo", "echo e");
+
+        // the value of an alias is looked up too, but never the alias within its own value
+        assert_eq!(parse("tsh_test_ll x"), "tsh_test_ls -F -l x");
+        assert_eq!(parse("tsh_test_ls"), "tsh_test_ls -F");
+        assert_eq!(parse("tsh_test_a"), "tsh_test_a");
+        assert_eq!(parse("tsh_test_a; tsh_test_b"), "tsh_test_a; tsh_test_b");
+
+        // after an alias ending in a blank, the next word is an alias too
+        assert_eq!(parse("tsh_test_sudo tsh_test_echo x"), "sudo echo e x");
+        assert_eq!(parse("tsh_test_sudo tsh_test_ll"), "sudo tsh_test_ls -F -l");
+        assert_eq!(parse("tsh_test_nice tsh_test_echo"), "nice tsh_test_echo");
+        assert_eq!(
+            parse("tsh_test_sudo x tsh_test_echo"),
+            "sudo x tsh_test_echo"
+        );
+
+        // only the name of the command is, and only unquoted
+        assert_eq!(parse("A=1 tsh_test_echo"), "A=1 echo e");
+        assert_eq!(parse("x | tsh_test_echo"), "x | echo e");
+        assert_eq!(parse("echo tsh_test_echo"), "echo tsh_test_echo");
+        assert_eq!(parse("\\tsh_test_echo"), "\\tsh_test_echo");
+        assert_eq!(parse("'tsh_test_echo'"), "'tsh_test_echo'");
     }
 }
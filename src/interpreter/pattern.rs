@@ -0,0 +1,228 @@
+use std::fs;
+
+/// Matches a text against a shell pattern, where `*` matches anything, `?` matches a single
+/// char, `[...]` matches a set of chars (`[!...]` or `[^...]` negate it) and a backslash makes
+/// the next char literal.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    matches_from(&pattern, &text)
+}
+
+fn matches_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => {
+            // many stars in a row are the same as only one
+            let rest = &pattern[pattern.iter().take_while(|&&c| c == '*').count()..];
+            if rest.is_empty() {
+                return true;
+            }
+
+            (0..=text.len()).any(|i| matches_from(rest, &text[i..]))
+        }
+        Some('?') => !text.is_empty() && matches_from(&pattern[1..], &text[1..]),
+        Some('[') => match (text.first(), match_bracket(pattern, text.first())) {
+            (Some(_), Some((true, len))) => matches_from(&pattern[len..], &text[1..]),
+            (_, Some((false, _))) | (None, Some(_)) => false,
+            // without the closing bracket, the [ is just a [
+            (_, None) => text.first() == Some(&'[') && matches_from(&pattern[1..], &text[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && matches_from(&pattern[2..], &text[1..])
+        }
+        Some(c) => text.first() == Some(c) && matches_from(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Returns if the char matches the bracket expression at the start of the pattern, and how
+/// many chars of the pattern the expression takes. None if the bracket is never closed.
+fn match_bracket(pattern: &[char], c: Option<&char>) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let current = *pattern.get(i)?;
+        if current == ']' && !first {
+            break;
+        }
+        first = false;
+
+        if current == '['
+            && pattern.get(i + 1) == Some(&':')
+            && let Some(len) = pattern[i + 2..]
+                .windows(2)
+                .position(|window| window == [':', ']'])
+        {
+            // [:class:]
+            let class = pattern[i + 2..i + 2 + len].iter().collect::<String>();
+            matched |= c.is_some_and(|&c| matches_class(&class, c));
+            i += len + 4;
+            continue;
+        }
+
+        let (start, len) = match current {
+            '\\' => (*pattern.get(i + 1)?, 2),
+            _ => (current, 1),
+        };
+        i += len;
+
+        if pattern.get(i) == Some(&'-') && pattern.get(i + 1).is_some_and(|&end| end != ']') {
+            let end = pattern[i + 1];
+            matched |= c.is_some_and(|&c| start <= c && c <= end);
+            i += 2;
+        } else {
+            matched |= c == Some(&start);
+        }
+    }
+
+    Some((matched != negated, i + 1))
+}
+
+fn matches_class(class: &str, c: char) -> bool {
+    match class {
+        "alpha" => c.is_alphabetic(),
+        "digit" => c.is_ascii_digit(),
+        "alnum" => c.is_alphanumeric(),
+        "upper" => c.is_uppercase(),
+        "lower" => c.is_lowercase(),
+        "space" => c.is_whitespace(),
+        "blank" => c == ' ' || c == '\t',
+        "punct" => c.is_ascii_punctuation(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+/// Expands a pattern into the paths that match it, sorted. A `**` component goes through any
+/// number of directories, or as the last one gives everything below. Names starting with a dot
+/// are only matched by a dot, unless `dotglob` says otherwise.
+pub fn expand_path(pattern: &str, dotglob: bool) -> Vec<String> {
+    let components = pattern.split('/').collect::<Vec<_>>();
+    let mut paths = vec![String::new()];
+
+    for (i, &component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        paths = if i == 0 && component.is_empty() && !last {
+            // absolute
+            vec![String::from("/")]
+        } else if component == "**" {
+            paths
+                .iter()
+                .flat_map(|path| descend(path, last, dotglob))
+                .collect()
+        } else if !has_pattern(component) {
+            let name = unescape(component);
+            paths
+                .iter()
+                .map(|path| join(path, &name))
+                .filter(|path| fs::symlink_metadata(path).is_ok())
+                .collect()
+        } else {
+            paths
+                .iter()
+                .flat_map(|path| matching_entries(path, component, dotglob))
+                .collect()
+        };
+
+        if paths.is_empty() {
+            break;
+        }
+    }
+
+    paths.sort();
+    paths
+}
+
+fn has_pattern(component: &str) -> bool {
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+fn unescape(component: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() || dir.ends_with('/') {
+        format!("{dir}{name}")
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// The names in the directory, the current one if empty, that aren't hidden from the pattern.
+fn visible_entries(dir: &str, dotglob: bool, dot: bool) -> Vec<(String, bool)> {
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return vec![];
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') && !dotglob && !dot {
+                return None;
+            }
+            // the links are not followed, a link to a parent would never end
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            Some((name, is_dir))
+        })
+        .collect()
+}
+
+fn matching_entries(dir: &str, component: &str, dotglob: bool) -> Vec<String> {
+    visible_entries(dir, dotglob, component.starts_with('.'))
+        .into_iter()
+        .filter(|(name, _)| matches(component, name))
+        .map(|(name, _)| join(dir, &name))
+        .collect()
+}
+
+/// The directory and every directory below it, or everything below it with `everything`.
+fn descend(dir: &str, everything: bool, dotglob: bool) -> Vec<String> {
+    let mut found = if everything {
+        vec![]
+    } else {
+        vec![dir.to_owned()]
+    };
+
+    for (name, is_dir) in visible_entries(dir, dotglob, false) {
+        let path = join(dir, &name);
+        if is_dir {
+            found.extend(descend(&path, everything, dotglob));
+            if everything {
+                found.push(path);
+            }
+        } else if everything {
+            found.push(path);
+        }
+    }
+
+    found
+}
@@ -1,43 +1,291 @@
+mod completion;
+mod editor;
+mod hash;
+mod history;
 mod interpreter;
+mod prompt;
 mod utils;
 
-use std::{io::Write, thread};
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, IsTerminal},
+    path::{Path, PathBuf},
+    process::exit,
+};
 
 use anyhow::Result;
-use interpreter::executor;
+use editor::Editor;
+use interpreter::{
+    environment::{
+        get_last_status, get_var, init_shell_pid, is_option_set, set_last_status,
+        set_positional_params, set_shell_name,
+    },
+    executor,
+};
+use nix::unistd::{Whence, lseek, read};
 
-use crate::utils::{EXECUTABLES, POISONED_LOCK_MSG_ERR, STDIN, STDOUT, get_executables_in_path};
+use crate::utils::{POISONED_LOCK_MSG_ERR, STDIN};
+
+/// Where the commands come from.
+enum Input {
+    // The user typing in the terminal, with prompts and line editing
+    Terminal(Editor),
+    // The standard input of the shell, shared with the commands that read it. When it can't
+    // seek back, it's read a byte at a time, to leave them what comes after the command
+    Stdin { seekable: bool },
+    // A script file or the string of -c
+    Script(Box<dyn BufRead>),
+}
+
+impl Input {
+    fn read_line(&mut self, prompt: &str, buffer: &mut String) -> io::Result<usize> {
+        match self {
+            Self::Terminal(editor) => editor.read_line(prompt, buffer),
+            Self::Stdin { seekable } => {
+                // Held so no builtin reads it at the same time
+                let _stdin = STDIN.lock().expect(POISONED_LOCK_MSG_ERR);
+                read_stdin_line(*seekable, buffer)
+            }
+            Self::Script(reader) => reader.read_line(buffer),
+        }
+    }
+}
 
 fn main() -> Result<()> {
+    init_shell_pid();
+
+    // tsh [--login] [--norc] [--rcfile file] [script [args...]] | ... -c command [name [args...]]
+    let mut args = env::args();
+    // login(1) starts the shell with a `-` before its name
+    let mut login = args.next().is_some_and(|name| name.starts_with('-'));
+    let mut args = args.collect::<Vec<_>>();
+
+    let mut norc = false;
+    let mut rcfile = None;
+    let mut options = 0;
+    while let Some(option) = args.get(options) {
+        match option.as_str() {
+            "-l" | "--login" => login = true,
+            "--norc" => norc = true,
+            "--rcfile" => {
+                let Some(file) = args.get(options + 1) else {
+                    eprintln!("tsh: --rcfile: option requires an argument");
+                    exit(2);
+                };
+                rcfile = Some(PathBuf::from(file));
+                options += 1;
+            }
+            "--" => {
+                options += 1;
+                break;
+            }
+            _ => break,
+        }
+        options += 1;
+    }
+    args.drain(..options);
+
+    let input = match args.first().map(String::as_str) {
+        Some("-c") => {
+            let Some(command) = args.get(1) else {
+                eprintln!("tsh: -c: option requires an argument");
+                exit(2);
+            };
+            if let Some(name) = args.get(2) {
+                set_shell_name(name);
+            }
+            set_positional_params(args.iter().skip(3).cloned().collect());
+
+            Input::Script(Box::new(Cursor::new(command.clone())))
+        }
+        Some(script) => {
+            let file = match File::open(script) {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("tsh: {script}: {e}");
+                    exit(127);
+                }
+            };
+            set_shell_name(script);
+            set_positional_params(args[1..].to_vec());
+
+            Input::Script(Box::new(BufReader::new(file)))
+        }
+        // Only a terminal typing into the shell gets prompts and job control
+        None if io::stdin().is_terminal() => Input::Terminal(Editor::new()),
+        None => Input::Stdin {
+            seekable: lseek(io::stdin(), 0, Whence::SeekCur).is_ok(),
+        },
+    };
+
+    let interactive = matches!(input, Input::Terminal(_));
+    executor::signals::init(interactive);
+    if interactive {
+        executor::jobs::init();
+        // Scripts look for their commands in PATH as they run them, only the completion of
+        // the terminal needs every executable there
+        hash::init();
+    }
+    // before the history, the startup files may set where and how much of it is kept
+    if login {
+        source_profiles();
+    }
+    if interactive && !norc {
+        source_rc_files(rcfile);
+    }
+    if interactive {
+        history::init();
+    }
+
+    run(input, interactive)?;
+
+    // The shell ends with the status of its last command
+    executor::exit_shell(get_last_status());
+}
+
+fn run(mut input: Input, interactive: bool) -> Result<()> {
     let mut buffer = String::new();
+    // the end of file typed in a row, for IGNOREEOF
+    let mut eofs = 0;
 
     loop {
-        thread::spawn(|| {
-            // FIXME: This seems a little bad. I guess it should be replaced to an mechanism of communication
-            // with aptd (or the daemon of the current package manager of the system). Only if we have more
-            // packages installed, we execute this, otherwise no.
-            let executables = EXECUTABLES.lock().expect(POISONED_LOCK_MSG_ERR);
-            let mut executables = executables.borrow_mut();
+        if buffer.is_empty() {
+            // Look again only at the directories of PATH that changed since the last command
+            if interactive {
+                hash::refresh();
+            }
+            // Tell about the jobs that changed since the last prompt
+            executor::jobs::notify();
+        }
+
+        // only the terminal shows a prompt, there's no need to build it for anything else
+        let prompt = match interactive {
+            false => String::new(),
+            true if buffer.is_empty() => {
+                prompt::run_prompt_command();
+                prompt::primary()
+            }
+            true => prompt::secondary(),
+        };
 
-            executables.clear();
-            executables.extend(get_executables_in_path());
-        });
+        let read = match input.read_line(&prompt, &mut buffer) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                // Ctrl-C throws away everything typed for the command
+                set_last_status(130);
+                buffer.clear();
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
 
-        {
-            let stdout = STDOUT.lock().expect(POISONED_LOCK_MSG_ERR);
-            let stdin = STDIN.lock().expect(POISONED_LOCK_MSG_ERR);
-            let stdin = stdin.borrow_mut();
-            let mut stdout = stdout.borrow_mut();
+        if read == 0 {
+            if !buffer.is_empty() {
+                // Nothing more will come to complete the pending input
+                eprintln!("Unexpected end of file while reading the command");
+                set_last_status(2);
+                buffer.clear();
+            }
 
-            stdout.write_all(b"$ ")?;
-            stdout.flush()?;
-            stdin.read_line(&mut buffer)?;
+            if !interactive {
+                return Ok(());
+            }
+
+            if eofs < ignored_eofs() {
+                eofs += 1;
+                eprintln!("\nUse \"exit\" to leave the shell.");
+                continue;
+            }
+
+            eprintln!("exit");
+            return Ok(());
         }
+        eofs = 0;
 
-        if let Err(e) = executor::execute(&buffer) {
-            eprintln!("{}", e)
+        match executor::execute(&buffer) {
+            Err(e) if executor::is_incomplete(&e) => {
+                // Keep the buffer, the next lines are appended to it
+                continue;
+            }
+            Err(e) => eprintln!("{}", e),
+            Ok(()) => {}
         }
 
         buffer.clear();
     }
 }
+
+/// Reads a line of the standard input without going past it, as the commands run by the
+/// shell read the same input and must find there what comes after. A file is read by blocks
+/// and the rest is given back seeking backwards, a pipe or a terminal one byte at a time.
+fn read_stdin_line(seekable: bool, buffer: &mut String) -> io::Result<usize> {
+    let mut line = vec![];
+    let mut block = [0u8; 4096];
+    let size = if seekable { block.len() } else { 1 };
+
+    loop {
+        let read = read(io::stdin(), &mut block[..size])?;
+        if read == 0 {
+            break;
+        }
+
+        match block[..read].iter().position(|&b| b == b'\n') {
+            Some(end) => {
+                line.extend_from_slice(&block[..=end]);
+                let rest = (read - end - 1) as i64;
+                if rest > 0 {
+                    lseek(io::stdin(), -rest, Whence::SeekCur)?;
+                }
+                break;
+            }
+            None => line.extend_from_slice(&block[..read]),
+        }
+    }
+
+    buffer.push_str(&String::from_utf8_lossy(&line));
+    Ok(line.len())
+}
+
+/// Runs the files of a login shell, the one for the whole system and the one of the user.
+fn source_profiles() {
+    source_if_exists(Path::new("/etc/tsh_profile"));
+    if let Some(home) = get_var("HOME") {
+        source_if_exists(&Path::new(&home).join(".tsh_profile"));
+    }
+}
+
+/// Runs the files of an interactive shell, `/etc/tshrc` and `~/.tshrc`, or only the one given
+/// by `--rcfile`.
+fn source_rc_files(rcfile: Option<PathBuf>) {
+    if let Some(rcfile) = rcfile {
+        if let Err(e) = executor::source(&rcfile) {
+            eprintln!("tsh: {e}");
+        }
+        return;
+    }
+
+    source_if_exists(Path::new("/etc/tshrc"));
+    if let Some(home) = get_var("HOME") {
+        source_if_exists(&Path::new(&home).join(".tshrc"));
+    }
+}
+
+/// Every startup file is optional, only the errors of the ones that are there are reported.
+fn source_if_exists(path: &Path) {
+    if path.is_file()
+        && let Err(e) = executor::source(path)
+    {
+        eprintln!("tsh: {e}");
+    }
+}
+
+/// How many ends of file in a row an interactive shell ignores before leaving. With IGNOREEOF
+/// set but without a number, or with `set -o ignoreeof`, it's 10.
+fn ignored_eofs() -> usize {
+    match get_var("IGNOREEOF") {
+        Some(value) => value.parse().unwrap_or(10),
+        None if is_option_set("ignoreeof") => 10,
+        None => 0,
+    }
+}
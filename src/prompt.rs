@@ -0,0 +1,192 @@
+use std::{
+    env, fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use nix::{
+    libc,
+    unistd::{User, getuid},
+};
+
+use crate::{
+    history,
+    interpreter::{
+        environment::{get_last_status, get_var, set_last_status},
+        executor::{self, jobs},
+    },
+    utils::abbreviate_home,
+};
+
+// Around what the terminal doesn't show, so the line editor leaves it out of the width
+pub const START_INVISIBLE: char = '\x01';
+pub const END_INVISIBLE: char = '\x02';
+
+/// Runs the commands of `PROMPT_COMMAND`, before every prompt for a new command. The status of
+/// the last command the user ran is kept, for `$?` and `\?`.
+pub fn run_prompt_command() {
+    let Some(command) = get_var("PROMPT_COMMAND").filter(|command| !command.trim().is_empty())
+    else {
+        return;
+    };
+
+    let status = get_last_status();
+    if let Err(e) = executor::evaluate(&command) {
+        eprintln!("PROMPT_COMMAND: {e}");
+    }
+    set_last_status(status);
+}
+
+/// The prompt for a new command, `PS1`.
+pub fn primary() -> String {
+    get_var("PS1").map_or_else(|| "$ ".to_owned(), |ps1| render(&ps1))
+}
+
+/// The prompt for the lines that go on with an incomplete command, `PS2`.
+pub fn secondary() -> String {
+    get_var("PS2").map_or_else(|| "> ".to_owned(), |ps2| render(&ps2))
+}
+
+/// Replaces the escapes of a prompt:
+///
+/// - `\u` the user, `\h` the host up to the first `.` and `\H` the whole host
+/// - `\w` the current directory with the home as `~`, `\W` only its last part
+/// - `\?` the status of the last command, `\j` how many jobs there are
+/// - `\g` the branch of the git repository of the current directory, if any
+/// - `\t` the time as HH:MM:SS, `\T` in 12 hours, `\@` in 12 hours with am/pm, `\A` as HH:MM,
+///   `\d` the date, as "Tue May 26", and `\D{format}` as strftime formats it
+/// - `\$` a `#` for root and a `$` for everyone else, `\s` the name of the shell
+/// - `\n` a newline, `\e` the escape char, `\a` the bell, `\nnn` the char with that octal code
+///   and `\\` a backslash
+/// - `\[` and `\]` around the chars that take no room, as the ANSI colors
+pub fn render(template: &str) -> String {
+    let mut prompt = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            prompt.push(c);
+            continue;
+        }
+
+        let Some(escape) = chars.next() else {
+            prompt.push('\\');
+            break;
+        };
+        match escape {
+            'u' => prompt.push_str(&user()),
+            'h' => prompt.push_str(hostname().split('.').next().unwrap_or_default()),
+            'H' => prompt.push_str(&hostname()),
+            'w' => prompt.push_str(&current_directory()),
+            'W' => {
+                let directory = current_directory();
+                match directory.rsplit_once('/') {
+                    Some((_, "")) | None => prompt.push_str(&directory),
+                    Some((_, last)) => prompt.push_str(last),
+                }
+            }
+            '?' => prompt.push_str(&get_last_status().to_string()),
+            'j' => prompt.push_str(&jobs::count().to_string()),
+            'g' => prompt.push_str(&git_branch().unwrap_or_default()),
+            't' => prompt.push_str(&now("%H:%M:%S")),
+            'T' => prompt.push_str(&now("%I:%M:%S")),
+            '@' => prompt.push_str(&now("%I:%M %p")),
+            'A' => prompt.push_str(&now("%H:%M")),
+            'd' => prompt.push_str(&now("%a %b %d")),
+            'D' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let format = chars.by_ref().take_while(|&c| c != '}').collect::<String>();
+                // with no format, the time as the locale writes it
+                prompt.push_str(&now(if format.is_empty() { "%X" } else { &format }));
+            }
+            '$' => prompt.push(if getuid().is_root() { '#' } else { '$' }),
+            's' => prompt.push_str("tsh"),
+            'n' => prompt.push('\n'),
+            'e' => prompt.push('\x1b'),
+            'a' => prompt.push('\x07'),
+            '0'..='7' => {
+                let mut code = escape.to_digit(8).unwrap_or_default();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                prompt.extend(char::from_u32(code));
+            }
+            '\\' => prompt.push('\\'),
+            '[' => prompt.push(START_INVISIBLE),
+            ']' => prompt.push(END_INVISIBLE),
+            other => {
+                prompt.push('\\');
+                prompt.push(other);
+            }
+        }
+    }
+
+    prompt
+}
+
+fn user() -> String {
+    User::from_uid(getuid())
+        .ok()
+        .flatten()
+        .map(|user| user.name)
+        .or_else(|| get_var("USER"))
+        .unwrap_or_default()
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY:
+    // gethostname writes at most the len of the buffer, which is owned by this function.
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return String::new();
+    }
+
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+fn current_directory() -> String {
+    match get_var("PWD") {
+        Some(pwd) if !pwd.is_empty() => abbreviate_home(pwd.as_ref()),
+        _ => env::current_dir()
+            .map(|directory| abbreviate_home(&directory))
+            .unwrap_or_default(),
+    }
+}
+
+fn now(format: &str) -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default();
+    history::format_timestamp(seconds, format)
+}
+
+/// The branch checked out in the repository of the current directory, or the short hash of
+/// the commit when there's none. It's read from `HEAD`, without running git.
+fn git_branch() -> Option<String> {
+    let current = env::current_dir().ok()?;
+    let directory = current.ancestors().find(|dir| dir.join(".git").exists())?;
+    let mut git_dir = directory.join(".git");
+
+    // the worktrees and the submodules have a file pointing to the real one
+    if git_dir.is_file() {
+        let link = fs::read_to_string(&git_dir).ok()?;
+        git_dir = directory.join(link.strip_prefix("gitdir:")?.trim());
+    }
+
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    Some(match head.strip_prefix("ref: ") {
+        Some(reference) => reference
+            .strip_prefix("refs/heads/")
+            .unwrap_or(reference)
+            .to_owned(),
+        None => head.chars().take(7).collect(),
+    })
+}
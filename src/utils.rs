@@ -1,25 +1,22 @@
 use std::{
     cell::RefCell,
     env,
-    fs::File,
     io::{Stderr, Stdin, Stdout, stderr, stdin, stdout},
-    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
-    sync::{Arc, Mutex},
-    thread,
+    sync::Mutex,
 };
 
 use anyhow::Result;
 use lazy_static::lazy_static;
 
+use crate::interpreter::environment::get_var;
+
 pub const POISONED_LOCK_MSG_ERR: &str = "Poisoned lock found";
 
 lazy_static! {
     pub static ref STDOUT: Mutex<RefCell<Stdout>> = Mutex::new(RefCell::new(stdout()));
     pub static ref STDERR: Mutex<RefCell<Stderr>> = Mutex::new(RefCell::new(stderr()));
     pub static ref STDIN: Mutex<RefCell<Stdin>> = Mutex::new(RefCell::new(stdin()));
-    pub static ref EXECUTABLES: Mutex<RefCell<Vec<PathBuf>>> =
-        Mutex::new(RefCell::new(get_executables_in_path()));
 }
 
 pub fn report_line_err(args: Option<&str>) {
@@ -34,60 +31,14 @@ pub fn get_cwd() -> Result<PathBuf> {
     Ok(env::current_dir()?)
 }
 
-#[inline(always)]
-pub fn get_env(key: &str) -> Result<String> {
-    Ok(env::var(key)?)
-}
-
-pub fn get_executable_path<'a>(
-    executable_name: &str,
-    executables: &'a [PathBuf],
-) -> Option<&'a Path> {
-    // We cannot directly use EXECUTABLES cause we don't an way
-    // to specify lifetimes to it, but with an argument list we have
-
-    for executable in executables.iter() {
-        if let Some(executable_file_name) = executable.file_name() {
-            if executable_file_name == executable_name {
-                return Some(executable);
-            }
-        }
-    }
-
-    None
-}
-
-pub fn get_executables_in_path() -> Vec<PathBuf> {
-    // FIXME: Put the executables in some tsh.sources file to avoid loading
-    // this everytime that bash starts up, and update or remove entries using
-    // communication with aptd (or the current daemon of the package manager)
-    // via dbus
-
-    let executables = Arc::new(Mutex::new(vec![]));
-
-    if let Ok(path) = get_env("PATH") {
-        for dir in env::split_paths(&path) {
-            let clonable = executables.clone();
-            thread::spawn(move || {
-                // maybe the path in PATH variable does not exist
-                if let Ok(dir_entries) = std::fs::read_dir(Path::new(&dir)) {
-                    for entry in dir_entries.flatten() {
-                        // according to docs, if we have an IO error during
-                        // the iteration, the item will return Error
-                        if is_executable(entry.path().as_path()).is_ok() {
-                            let mut vec = clonable.lock().expect(POISONED_LOCK_MSG_ERR);
-                            vec.push(entry.path());
-                        }
-                    }
-                }
-            });
-        }
+/// Replaces the home directory at the start of the path by `~`.
+pub fn abbreviate_home(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    match get_var("HOME") {
+        Some(home) if !home.is_empty() && path.starts_with(&home) => match &path[home.len()..] {
+            rest if rest.is_empty() || rest.starts_with('/') => format!("~{rest}"),
+            _ => path.into_owned(),
+        },
+        _ => path.into_owned(),
     }
-
-    let executables = executables.lock().expect(POISONED_LOCK_MSG_ERR);
-    executables.clone()
-}
-
-fn is_executable(path: &Path) -> Result<bool> {
-    Ok(File::open(path)?.metadata()?.permissions().mode() & 0o111 != 0)
 }
@@ -0,0 +1,43 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Runs the script on a shell reading it from its standard input, and returns what it wrote.
+fn output_of(script: &str) -> String {
+    let mut shell = Command::new(env!("CARGO_BIN_EXE_tsh"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    shell
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+
+    String::from_utf8(shell.wait_with_output().unwrap().stdout).unwrap()
+}
+
+#[test]
+fn aliases_apply_from_the_next_line() {
+    let output = output_of(
+        "alias say='echo said' again='say '\n\
+         say it\n\
+         again say\n\
+         alias\n\
+         unalias -a\n\
+         alias\n\
+         say it\n",
+    );
+
+    assert_eq!(
+        output,
+        "said it\n\
+         said echo said\n\
+         alias again='say '\n\
+         alias say='echo said'\n"
+    );
+}
@@ -0,0 +1,60 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{Read, Write},
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use nix::{pty::openpty, unistd::setsid};
+
+/// Runs an interactive shell on a new terminal, typing the lines one after the other, and
+/// returns the home it ran with, to look at what it left there. Every test needs a name of
+/// its own for it.
+pub fn run_interactive(name: &str, lines: &[&str]) -> PathBuf {
+    let home = env::temp_dir().join(format!("tsh-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&home);
+    fs::create_dir_all(&home).unwrap();
+
+    let pty = openpty(None, None).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_tsh"));
+    command
+        .env("HOME", &home)
+        .env_remove("HISTFILE")
+        .current_dir(&home)
+        .stdin(Stdio::from(pty.slave.try_clone().unwrap()))
+        .stdout(Stdio::from(pty.slave.try_clone().unwrap()))
+        .stderr(Stdio::from(pty.slave));
+    // SAFETY:
+    // setsid only makes the child lead a session of its own, away from the terminal of the
+    // tests, it allocates nothing.
+    unsafe {
+        command.pre_exec(|| setsid().map(|_| ()).map_err(Into::into));
+    }
+    let mut shell = command.spawn().unwrap();
+    drop(command);
+
+    // The terminal must be drained, or the shell would block writing to it
+    let mut terminal = File::from(pty.master);
+    let mut reader = terminal.try_clone().unwrap();
+    thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        while reader.read(&mut buffer).is_ok_and(|read| read > 0) {}
+    });
+
+    for line in lines {
+        thread::sleep(Duration::from_millis(300));
+        terminal.write_all(format!("{line}\r").as_bytes()).unwrap();
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while shell.try_wait().unwrap().is_none() {
+        assert!(Instant::now() < deadline, "the shell didn't exit");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    home
+}
@@ -0,0 +1,21 @@
+use std::process::{Command, Output};
+
+fn run(script: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tsh"))
+        .args(["-c", script])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn an_unset_parameter_ends_the_script() {
+    let output = run("echo before; f() { echo ${TSH_TEST_UNSET?gone}; }; f; echo after");
+    assert_eq!(output.status.code(), Some(127));
+    assert_eq!(output.stdout, b"before\n");
+    assert_eq!(output.stderr, b"TSH_TEST_UNSET: gone\n");
+
+    // a stage of a pipeline or a command substitution is a child, only the child ends
+    let output = run("echo ${TSH_TEST_UNSET?} | cat; echo $(echo ${TSH_TEST_UNSET?}) after");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"after\n");
+}
@@ -0,0 +1,17 @@
+mod common;
+
+use std::fs;
+
+#[test]
+fn exit_in_a_pipeline_leaves_the_history_to_the_shell() {
+    let home = common::run_interactive("history", &["echo one", "echo x | exit 3", "exit"]);
+    let history = fs::read_to_string(home.join(".tsh_history")).unwrap();
+    let _ = fs::remove_dir_all(&home);
+
+    let commands = history
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>();
+
+    assert_eq!(commands, ["echo one", "echo x | exit 3", "exit"]);
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use std::fs;
+
+#[test]
+fn wait_takes_the_status_of_the_jobs_in_background() {
+    let home = common::run_interactive(
+        "jobs",
+        &[
+            "echo 'sleep 1; exit 7' > job.sh",
+            "^sh job.sh",
+            "^sleep 1",
+            "jobs > listed",
+            "wait %1; echo $? > first",
+            "wait; echo $? > all",
+            "jobs > left",
+            "exit",
+        ],
+    );
+    let read = |name| fs::read_to_string(home.join(name)).unwrap();
+    let (listed, first, all, left) = (read("listed"), read("first"), read("all"), read("left"));
+    let _ = fs::remove_dir_all(&home);
+
+    assert_eq!(
+        listed.lines().collect::<Vec<_>>(),
+        [
+            format!("[1]-  {:<24}^sh job.sh", "Running"),
+            format!("[2]+  {:<24}^sleep 1", "Running"),
+        ]
+    );
+    assert_eq!(first, "7\n");
+    assert_eq!(all, "0\n");
+    assert_eq!(left, "");
+}